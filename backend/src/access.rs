@@ -0,0 +1,151 @@
+// Keyless access: the renter of an active booking gets an access grant binding
+// their ed25519 public key to the lock device of the car for the booking period.
+// Each lock trusts an authority key registered with it: the owner side signs the
+// grant message with that key, the contract checks the signature before storing
+// the grant, and the lock verifies it offline together with the renter's
+// signature of a challenge against the granted key. A lock gateway can also ask
+// the contract whether a key is currently authorized for a car; when a booking
+// is extended the stored grant follows at once, while the signed copy used
+// offline must be refreshed by issuing the grant again.
+use crate::*;
+use near_sdk::env::ed25519_verify;
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{CurveType, PublicKey};
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct AccessGrant {
+    pub booking_id: String,
+    pub car_id: String,
+    pub device_id: String,
+    pub public_key: String, // "ed25519:<base58>"
+    pub valid_from: u64,
+    pub valid_until: u64,
+    pub signed_until: u64, // end of the period covered by the signature
+    pub signature: Vec<u8>, // of the grant message by the lock's authority key
+    pub issued_at: u64,
+    pub revoked: bool,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // register_lock pairs a car with the device id of its smart lock and the
+    // ed25519 key the lock trusts to sign access grants
+    #[handle_result]
+    pub fn register_lock(&mut self, car_id: String, device_id: String, authority_key: PublicKey) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        if authority_key.curve_type() != CurveType::ED25519 {
+            return Err(Error::InvalidPublicKey);
+        }
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.lock_device_id = Some(device_id.clone());
+        car.lock_authority_key = Some(String::from(&authority_key));
+        log!("Event: LockRegistered, car_id: {}, device_id: {}, authority_key: {}", car_id, device_id, String::from(&authority_key));
+        Ok(format!("Lock '{}' registered for car '{}'", device_id, car_id))
+    }
+
+    // issue_access_grant lets the renter of an active booking open the car with
+    // the given key until the end of the booking, replacing any previous grant;
+    // the grant message must be signed with the lock's authority key
+    #[handle_result]
+    pub fn issue_access_grant(&mut self, booking_id: String, public_key: PublicKey, signature: Base64VecU8) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if booking.status != BookingStatus::Active {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if public_key.curve_type() != CurveType::ED25519 {
+            return Err(Error::InvalidPublicKey);
+        }
+        let car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        let device_id: String = car.lock_device_id.clone().ok_or(Error::LockNotRegistered)?;
+        let authority_key: String = car.lock_authority_key.clone().ok_or(Error::LockNotRegistered)?;
+        let public_key: String = String::from(&public_key);
+        let message: String = access_grant_message(&booking.car_id, &device_id, &booking_id, &public_key, booking.start_time, booking.end_time);
+        if !is_signed_by(&authority_key, message.as_bytes(), &signature.0) {
+            return Err(Error::InvalidSignature);
+        }
+        let grant = AccessGrant {
+            booking_id: booking_id.clone(),
+            car_id: booking.car_id.clone(),
+            device_id: device_id.clone(),
+            public_key,
+            valid_from: booking.start_time,
+            valid_until: booking.end_time,
+            signed_until: booking.end_time,
+            signature: signature.0,
+            issued_at: block_timestamp(),
+            revoked: false,
+        };
+        log!("Event: AccessGranted, booking_id: {}, car_id: {}, device_id: {}, public_key: {}, valid_from: {}, valid_until: {}", booking_id, grant.car_id, device_id, grant.public_key, grant.valid_from, grant.valid_until);
+        self.access_grants.insert(booking_id.clone(), grant);
+        Ok(format!("Access to car granted for booking {}", booking_id))
+    }
+
+    // revoke_access_grant can be called by the renter or on the owner side
+    #[handle_result]
+    pub fn revoke_access_grant(&mut self, booking_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        self.booking_party(booking, &caller, Permission::CheckInOut)?;
+        let grant: &mut AccessGrant = self.access_grants.get_mut(&booking_id).ok_or(Error::AccessGrantNotFound)?;
+        grant.revoked = true;
+        log!("Event: AccessRevoked, booking_id: {}, by: {}", booking_id, caller);
+        Ok(format!("Access grant of booking {} revoked", booking_id))
+    }
+
+    // read-only functions
+    pub fn get_access_grant(&self, booking_id: String) -> Option<AccessGrant> {
+        self.access_grants.get(&booking_id).cloned()
+    }
+
+    // get_access_grant_message returns the message the lock's authority key must
+    // sign to grant the key access for the current period of the booking
+    #[handle_result]
+    pub fn get_access_grant_message(&self, booking_id: String, public_key: String) -> Result<String, String> {
+        let booking = self.bookings.get(&booking_id).ok_or("Booking not found".to_string())?;
+        let device_id: String = self
+            .cars
+            .get(&booking.car_id)
+            .and_then(|car| car.lock_device_id.clone())
+            .ok_or("No smart lock registered for this car".to_string())?;
+        Ok(access_grant_message(&booking.car_id, &device_id, &booking_id, &public_key, booking.start_time, booking.end_time))
+    }
+
+    // is_key_authorized is queried by lock gateways: true while the key holds a
+    // grant for this device and its booking is still active
+    pub fn is_key_authorized(&self, car_id: String, device_id: String, public_key: String) -> bool {
+        let now: u64 = block_timestamp();
+        self.access_grants.values().any(|grant| {
+            grant.car_id == car_id
+                && grant.device_id == device_id
+                && grant.public_key == public_key
+                && !grant.revoked
+                && now >= grant.valid_from
+                && now <= grant.valid_until
+                && self.bookings.get(&grant.booking_id).is_some_and(|b| b.status == BookingStatus::Active)
+                && self.cars.get(&car_id).is_some_and(|car| car.lock_device_id.as_deref() == Some(device_id.as_str()))
+        })
+    }
+}
+
+// what the lock's authority key signs, the lock rebuilds it to check a grant offline
+fn access_grant_message(car_id: &str, device_id: &str, booking_id: &str, public_key: &str, valid_from: u64, valid_until: u64) -> String {
+    format!("access-grant:{}:{}:{}:{}:{}:{}", car_id, device_id, booking_id, public_key, valid_from, valid_until)
+}
+
+fn is_signed_by(authority_key: &str, message: &[u8], signature: &[u8]) -> bool {
+    let key: PublicKey = match authority_key.parse() {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    // the first byte of the key is its curve type
+    match (<[u8; 64]>::try_from(signature), <[u8; 32]>::try_from(&key.as_bytes()[1..])) {
+        (Ok(signature), Ok(key)) => ed25519_verify(&signature, message, &key),
+        _ => false,
+    }
+}
@@ -0,0 +1,204 @@
+// Batch onboarding and updates of cars. Every item gets its own result; in
+// atomic mode nothing is applied unless every item is valid, in best-effort
+// mode valid items are applied and the others reported. An atomic batch must
+// fit in the attached gas as a whole, a best-effort batch is processed item by
+// item until the gas runs short and the rest is reported as skipped.
+use crate::*;
+use near_sdk::env::{prepaid_gas, used_gas};
+use near_sdk::Gas;
+
+pub const MAX_BATCH_SIZE: usize = 200;
+// gas kept for each item, enough to list or update one car
+pub const GAS_PER_BATCH_ITEM: Gas = Gas::from_tgas(1);
+// gas kept aside to finish the call and return the results
+pub const GAS_BATCH_RESERVE: Gas = Gas::from_tgas(5);
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum BatchMode {
+    #[default]
+    Atomic,
+    BestEffort,
+}
+#[derive(Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CarInput {
+    pub car_id: String,
+    pub owner_id: String,
+    pub hourly_rate: u128,
+}
+// fields left empty are not changed
+#[derive(Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CarPatch {
+    pub car_id: String,
+    pub hourly_rate: Option<u128>,
+    pub late_fee_rate: Option<u128>,
+    pub grace_period: Option<u64>,
+    pub no_show_grace: Option<u64>,
+    pub no_show_fee: Option<u128>,
+    pub security_deposit: Option<u128>,
+    pub security_deposit_hold_hours: Option<u64>,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct BatchItemResult {
+    pub car_id: String,
+    pub success: bool,
+    pub message: String,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // add_cars lists several cars in one transaction
+    #[handle_result]
+    pub fn add_cars(&mut self, cars: Vec<CarInput>, mode: BatchMode) -> Result<Vec<BatchItemResult>, Error> {
+        let caller = predecessor_account_id().to_string();
+        // ids listed earlier in the batch count as taken
+        let mut listed: Vec<String> = Vec::new();
+        let results: Vec<BatchItemResult> = self.run_batch(
+            &cars,
+            mode,
+            |input| &input.car_id,
+            |contract, input| {
+                if listed.contains(&input.car_id) {
+                    return Err(Error::CarAlreadyExists);
+                }
+                contract.check_new_car(&caller, &input.car_id, &input.owner_id, input.hourly_rate)?;
+                listed.push(input.car_id.clone());
+                Ok(())
+            },
+            |contract, input| contract.insert_car(&input.car_id, &input.owner_id, input.hourly_rate),
+        )?;
+        log_batch("CarsAdded", &caller, &results);
+        Ok(results)
+    }
+
+    // update_cars changes rates, fees and deposits of several cars in one transaction
+    #[handle_result]
+    pub fn update_cars(&mut self, patches: Vec<CarPatch>, mode: BatchMode) -> Result<Vec<BatchItemResult>, Error> {
+        let caller = predecessor_account_id().to_string();
+        let results: Vec<BatchItemResult> = self.run_batch(
+            &patches,
+            mode,
+            |patch| &patch.car_id,
+            |contract, patch| contract.check_car_patch(&caller, patch),
+            |contract, patch| contract.apply_car_patch(patch),
+        )?;
+        log_batch("CarsUpdated", &caller, &results);
+        Ok(results)
+    }
+}
+
+impl CarSharing {
+    // check and apply every item of a batch: in atomic mode all items are
+    // checked before any is applied, in best-effort mode each valid item is
+    // applied right away while enough gas remains for one more item
+    fn run_batch<T>(
+        &mut self,
+        items: &[T],
+        mode: BatchMode,
+        car_id: impl Fn(&T) -> &String,
+        mut check: impl FnMut(&Self, &T) -> Result<(), Error>,
+        apply: impl Fn(&mut Self, &T),
+    ) -> Result<Vec<BatchItemResult>, Error> {
+        if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+            return Err(Error::InvalidLimit);
+        }
+        let mut results: Vec<BatchItemResult> = Vec::new();
+        match mode {
+            BatchMode::Atomic => {
+                // the whole batch must fit in the attached gas
+                if gas_left() < GAS_PER_BATCH_ITEM.saturating_mul(items.len() as u64).saturating_add(GAS_BATCH_RESERVE) {
+                    return Err(Error::InsufficientGas);
+                }
+                for item in items.iter() {
+                    results.push(item_result(car_id(item), check(self, item)));
+                }
+                if results.iter().all(|r| r.success) {
+                    items.iter().for_each(|item| apply(self, item));
+                } else {
+                    for result in results.iter_mut().filter(|r| r.success) {
+                        result.success = false;
+                        result.message = "Not applied, another item of the batch failed".to_string();
+                    }
+                }
+            }
+            BatchMode::BestEffort => {
+                // the first item that does not fit ends the batch, so the
+                // processed items are always a prefix of it
+                let mut out_of_gas: bool = false;
+                for item in items.iter() {
+                    out_of_gas = out_of_gas || gas_left() < GAS_PER_BATCH_ITEM.saturating_add(GAS_BATCH_RESERVE);
+                    if out_of_gas {
+                        results.push(BatchItemResult {
+                            car_id: car_id(item).clone(),
+                            success: false,
+                            message: "Skipped, not enough gas left".to_string(),
+                        });
+                        continue;
+                    }
+                    let result: BatchItemResult = item_result(car_id(item), check(self, item));
+                    if result.success {
+                        apply(self, item);
+                    }
+                    results.push(result);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn check_car_patch(&self, caller: &str, patch: &CarPatch) -> Result<(), Error> {
+        let car = self.cars.get(&patch.car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(caller, Scope::Car(car), Permission::SetRates)?;
+        if patch.hourly_rate == Some(0) {
+            return Err(Error::InvalidHourlyRate);
+        }
+        Ok(())
+    }
+
+    fn apply_car_patch(&mut self, patch: &CarPatch) {
+        if let Some(hourly_rate) = patch.hourly_rate {
+            // checked by check_car_patch, a zero rate never gets here
+            let _ = self.set_hourly_rate(&patch.car_id, hourly_rate);
+        }
+        if let Some(car) = self.cars.get_mut(&patch.car_id) {
+            if let Some(late_fee_rate) = patch.late_fee_rate {
+                car.late_fee_rate = late_fee_rate;
+            }
+            if let Some(grace_period) = patch.grace_period {
+                car.grace_period = grace_period;
+            }
+            if let Some(no_show_grace) = patch.no_show_grace {
+                car.no_show_grace = no_show_grace;
+            }
+            if let Some(no_show_fee) = patch.no_show_fee {
+                car.no_show_fee = no_show_fee;
+            }
+            if let Some(security_deposit) = patch.security_deposit {
+                car.security_deposit = security_deposit;
+            }
+            if let Some(hold_hours) = patch.security_deposit_hold_hours {
+                car.security_deposit_hold_hours = hold_hours;
+            }
+            log!("Event: CarUpdated, car_id: {}", patch.car_id);
+        }
+    }
+}
+
+fn gas_left() -> Gas {
+    prepaid_gas().saturating_sub(used_gas())
+}
+
+fn item_result(car_id: &str, check: Result<(), Error>) -> BatchItemResult {
+    BatchItemResult {
+        car_id: car_id.to_string(),
+        success: check.is_ok(),
+        message: match check {
+            Ok(()) => "Applied".to_string(),
+            Err(error) => error.message().to_string(),
+        },
+    }
+}
+
+fn log_batch(event: &str, caller: &str, results: &[BatchItemResult]) {
+    let applied: usize = results.iter().filter(|r| r.success).count();
+    log!("Event: {}, by: {}, applied: {}, failed: {}", event, caller, applied, results.len() - applied);
+}
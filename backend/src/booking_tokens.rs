@@ -0,0 +1,104 @@
+// Reservations as transferable tokens: a renter can mint a token for a booking
+// and hand it to another registered user before it starts. The recipient must
+// meet the car's requirements, and owners can turn transfers off per car.
+use crate::*;
+use crate::nft::{Token, TokenMetadata};
+
+#[near_bindgen]
+impl CarSharing {
+    // mint_booking_token makes a reservation transferable
+    #[handle_result]
+    pub fn mint_booking_token(&mut self, booking_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        self.check_booking_transferable(booking)?;
+        if booking.token_minted {
+            return Err(Error::BookingTokenExists);
+        }
+        if let Some(booking) = self.bookings.get_mut(&booking_id) {
+            booking.token_minted = true;
+        }
+        log!("Event: BookingTokenMinted, booking_id: {}, owner: {}", booking_id, caller);
+        Ok(format!("Token minted for booking {}", booking_id))
+    }
+
+    // transfer_booking hands a tokenized reservation, with the payments held
+    // for it, to another user who meets the car's requirements
+    #[handle_result]
+    pub fn transfer_booking(&mut self, booking_id: String, receiver_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if !booking.token_minted {
+            return Err(Error::BookingNotTransferable);
+        }
+        self.check_booking_transferable(booking)?;
+        if receiver_id == caller {
+            return Err(Error::InvalidReceiver);
+        }
+        let receiver: AccountId = receiver_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        if !self.is_user(&receiver) {
+            return Err(Error::InvalidUser);
+        }
+        // the recipient is held to the same requirements as a direct booking
+        let car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        self.check_requirements(car, &receiver_id)?;
+        let old_key: (String, String, u64) = (booking.car_id.clone(), booking.user_id.clone(), booking.start_time);
+        let new_key: (String, String, u64) = (booking.car_id.clone(), receiver_id.clone(), booking.start_time);
+        if self.booking_keys.get(&old_key).map(|id| id.as_str()) == Some(booking_id.as_str()) {
+            self.booking_keys.remove(&old_key);
+        }
+        self.booking_keys.insert(new_key, booking_id.clone());
+        if let Some(booking) = self.bookings.get_mut(&booking_id) {
+            booking.user_id = receiver_id.clone();
+        }
+        log!("Event: BookingTransferred, booking_id: {}, from: {}, to: {}", booking_id, caller, receiver_id);
+        Ok(format!("Booking {} transferred to '{}'", booking_id, receiver_id))
+    }
+
+    // set_booking_transfers lets owners allow or forbid reservation transfers on a car
+    #[handle_result]
+    pub fn set_booking_transfers(&mut self, car_id: String, enabled: bool) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::ApproveBookings)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.booking_transfers_disabled = !enabled;
+        log!("Event: BookingTransfersUpdated, car_id: {}, enabled: {}", car_id, enabled);
+        Ok(format!("Booking transfers of car '{}' {}", car_id, if enabled { "enabled" } else { "disabled" }))
+    }
+
+    // read-only functions
+    pub fn get_booking_token(&self, booking_id: String) -> Option<Token> {
+        self.bookings.get(&booking_id).filter(|b| b.token_minted).map(|b| Token {
+            token_id: b.booking_id.clone(),
+            owner_id: b.user_id.clone(),
+            metadata: Some(TokenMetadata {
+                title: Some(format!("Booking {} of car {}", b.booking_id, b.car_id)),
+                copies: Some(1),
+                starts_at: Some(b.start_time.to_string()),
+                expires_at: Some(b.end_time.to_string()),
+                ..Default::default()
+            }),
+        })
+    }
+}
+
+impl CarSharing {
+    // only reservations that have not started, on cars that allow it, can change hands
+    fn check_booking_transferable(&self, booking: &Booking) -> Result<(), Error> {
+        if booking.status != BookingStatus::Reserved || booking.picked_up() || block_timestamp() >= booking.start_time {
+            return Err(Error::BookingNotTransferable);
+        }
+        let car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        if car.booking_transfers_disabled {
+            return Err(Error::TransfersDisabled);
+        }
+        Ok(())
+    }
+}
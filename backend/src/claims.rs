@@ -0,0 +1,247 @@
+// Damage claims filed by owners against the security deposit of a returned
+// booking within a few days of its return. Accepted claims are paid from the
+// deposit, any shortfall becomes a debt of the renter; contested claims escalate
+// to the dispute flow and claims left unanswered past their deadline are settled
+// as accepted.
+use crate::*;
+
+pub const CLAIM_FILING_PERIOD: u64 = 3 * 24 * pricing::NANOS_PER_HOUR; // 3 days after return
+pub const CLAIM_RESPONSE_PERIOD: u64 = 3 * 24 * pricing::NANOS_PER_HOUR; // 3 days
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum ClaimStatus {
+    #[default]
+    Pending,
+    Accepted,
+    Contested,
+    Closed, // settled through a dispute
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct DamageClaim {
+    pub booking_id: String,
+    pub owner_id: String, // who filed the claim, payouts go to the owners of the booking
+    pub user_id: String,
+    pub amount: u128,
+    pub evidence_hash: String,
+    pub filed_at: u64,
+    pub respond_by: u64, // after this the claim can be settled as accepted
+    pub status: ClaimStatus,
+    pub outstanding: u128, // part of the claim not covered by the security deposit, owed by the renter
+    pub pending_coverage: u128, // part covered by insurance, paid from the pool once approved
+}
+
+impl Booking {
+    // owners can claim for damage for a while after return, whether or not
+    // the security deposit is still held
+    pub fn claim_window_open(&self, now: u64) -> bool {
+        self.returned_at.is_some_and(|returned_at| now < returned_at.saturating_add(CLAIM_FILING_PERIOD))
+    }
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // file_damage_claim lets the owner claim compensation for damage during the
+    // claim window of a returned booking, what the security deposit left cannot
+    // cover becomes a debt of the renter
+    #[handle_result]
+    pub fn file_damage_claim(&mut self, booking_id: String, amount: u128, evidence_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if self.booking_party(booking, &caller, Permission::Owner)? {
+            return Err(Error::Unauthorized);
+        }
+        if booking.status != BookingStatus::Completed || booking.returned_at.is_none() {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if !booking.claim_window_open(block_timestamp()) {
+            return Err(Error::ClaimWindowClosed);
+        }
+        if amount == 0 {
+            return Err(Error::InvalidClaimAmount);
+        }
+        if self.damage_claims.contains_key(&booking_id) {
+            return Err(Error::DamageClaimExists);
+        }
+        let user_id: String = booking.user_id.clone();
+        let now: u64 = block_timestamp();
+        self.damage_claims.insert(
+            booking_id.clone(),
+            DamageClaim {
+                booking_id: booking_id.clone(),
+                owner_id: caller.clone(),
+                user_id: user_id.clone(),
+                amount,
+                evidence_hash: evidence_hash.clone(),
+                filed_at: now,
+                respond_by: now + CLAIM_RESPONSE_PERIOD,
+                status: ClaimStatus::Pending,
+                outstanding: 0,
+                pending_coverage: 0,
+            },
+        );
+        log!("Event: DamageClaimFiled, booking_id: {}, owner: {}, user: {}, amount: {}, evidence_hash: {}", booking_id, caller, user_id, amount, evidence_hash);
+        Ok(format!("Damage claim of {} filed on booking {}", amount, booking_id))
+    }
+
+    // accept_damage_claim lets the renter accept a claim: it is paid from the
+    // security deposit, the rest of the deposit is released and any shortfall
+    // is recorded as a debt that blocks new bookings until paid
+    #[handle_result]
+    pub fn accept_damage_claim(&mut self, booking_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let claim = self.damage_claims.get(&booking_id).ok_or(Error::DamageClaimNotFound)?;
+        if claim.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if claim.status != ClaimStatus::Pending {
+            return Err(Error::InvalidClaimStatus);
+        }
+        self.accept_claim(&booking_id)?;
+        Ok(format!("Damage claim on booking {} accepted", booking_id))
+    }
+
+    // settle_unanswered_claim is permissionless: a claim the renter neither
+    // accepted nor contested before its deadline is settled as accepted
+    #[handle_result]
+    pub fn settle_unanswered_claim(&mut self, booking_id: String) -> Result<String, Error> {
+        let claim = self.damage_claims.get(&booking_id).ok_or(Error::DamageClaimNotFound)?;
+        if claim.status != ClaimStatus::Pending {
+            return Err(Error::InvalidClaimStatus);
+        }
+        if block_timestamp() <= claim.respond_by {
+            return Err(Error::ClaimResponseOpen);
+        }
+        self.accept_claim(&booking_id)?;
+        Ok(format!("Unanswered damage claim on booking {} settled", booking_id))
+    }
+
+    // contest_damage_claim escalates a claim to the dispute flow, the security
+    // deposit stays frozen until the dispute is settled
+    #[handle_result]
+    pub fn contest_damage_claim(&mut self, booking_id: String, reason: String, evidence_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let claim = self.damage_claims.get(&booking_id).ok_or(Error::DamageClaimNotFound)?;
+        if claim.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if claim.status != ClaimStatus::Pending {
+            return Err(Error::InvalidClaimStatus);
+        }
+        if block_timestamp() > claim.respond_by {
+            return Err(Error::ClaimWindowClosed);
+        }
+        self.open_dispute(booking_id.clone(), reason, evidence_hash)?;
+        if let Some(claim) = self.damage_claims.get_mut(&booking_id) {
+            claim.status = ClaimStatus::Contested;
+        }
+        log!("Event: DamageClaimContested, booking_id: {}, user: {}", booking_id, caller);
+        Ok(format!("Damage claim on booking {} contested", booking_id))
+    }
+
+    // pay_debt settles the renter's outstanding damage claims and booking
+    // balances, oldest first; any excess is refunded
+    #[payable]
+    #[handle_result]
+    pub fn pay_debt(&mut self) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let mut available: u128 = attached_deposit().as_yoctonear();
+        let user = self.users.get(&caller).ok_or(Error::UserNotFound)?;
+        if user.debt == 0 {
+            return Err(Error::NothingDue);
+        }
+        // (since, booking id, is a damage claim)
+        let mut debts: Vec<(u64, String, bool)> = self
+            .damage_claims
+            .values()
+            .filter(|c| c.user_id == caller && c.outstanding > 0)
+            .map(|c| (c.filed_at, c.booking_id.clone(), true))
+            .chain(
+                self.bookings
+                    .values()
+                    .filter(|b| b.user_id == caller && b.amount_due > 0)
+                    .map(|b| (b.returned_at.unwrap_or(b.end_time), b.booking_id.clone(), false)),
+            )
+            .collect();
+        debts.sort();
+        let mut total_paid: u128 = 0;
+        for (_, booking_id, is_claim) in debts {
+            if available == 0 {
+                break;
+            }
+            if is_claim {
+                if let Some(claim) = self.damage_claims.get_mut(&booking_id) {
+                    let paid: u128 = available.min(claim.outstanding);
+                    claim.outstanding -= paid;
+                    available -= paid;
+                    total_paid += paid;
+                    self.pay_booking_owners(&booking_id, paid);
+                }
+            } else if let Some(booking) = self.bookings.get_mut(&booking_id) {
+                let paid: u128 = available.min(booking.amount_due);
+                booking.amount_due -= paid;
+                available -= paid;
+                total_paid += paid;
+                self.pay_booking_owners(&booking_id, paid);
+            }
+        }
+        let remaining_debt: u128 = match self.users.get_mut(&caller) {
+            Some(user) => {
+                user.debt = user.debt.saturating_sub(total_paid);
+                user.debt
+            }
+            None => 0,
+        };
+        self.transfer(&caller, available);
+        log!("Event: DebtPaid, user: {}, paid: {}, remaining_debt: {}", caller, total_paid, remaining_debt);
+        Ok(format!("Paid {} of debt, {} remaining", total_paid, remaining_debt))
+    }
+
+    // read-only functions
+    #[handle_result]
+    pub fn get_damage_claim(&self, booking_id: String) -> Result<DamageClaim, String> {
+        self.damage_claims.get(&booking_id).cloned().ok_or("Damage claim not found".to_string())
+    }
+}
+
+impl CarSharing {
+    // pay an accepted claim from the security deposit, releasing the rest to the
+    // renter and recording any shortfall as their debt
+    pub(crate) fn accept_claim(&mut self, booking_id: &str) -> Result<(), Error> {
+        let claim = self.damage_claims.get(booking_id).ok_or(Error::DamageClaimNotFound)?;
+        let amount: u128 = claim.amount;
+        let owner_id: String = claim.owner_id.clone();
+        let user_id: String = claim.user_id.clone();
+        // insured bookings get the part above the deductible covered by the pool
+        // once the payout is approved
+        let booking = self.bookings.get(booking_id).ok_or(Error::BookingNotFound)?;
+        let covered: u128 = self.insurance_coverage(booking, amount);
+        let booking: &mut Booking = self.bookings.get_mut(booking_id).ok_or(Error::BookingNotFound)?;
+        let paid: u128 = (amount - covered).min(booking.security_deposit);
+        let released: u128 = booking.security_deposit - paid;
+        let shortfall: u128 = amount - covered - paid;
+        booking.security_deposit = 0;
+        if let Some(claim) = self.damage_claims.get_mut(booking_id) {
+            claim.status = ClaimStatus::Accepted;
+            claim.outstanding = shortfall;
+            claim.pending_coverage = covered;
+        }
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.debt += shortfall;
+        }
+        self.pay_booking_owners(booking_id, paid);
+        self.transfer(&user_id, released);
+        if covered > 0 {
+            log!("Event: InsurancePayoutRequested, booking_id: {}, owner: {}, amount: {}", booking_id, owner_id, covered);
+        }
+        log!("Event: DamageClaimAccepted, booking_id: {}, user: {}, paid: {}, debt: {}", booking_id, user_id, paid, shortfall);
+        Ok(())
+    }
+
+    // a pending or contested claim keeps the security deposit frozen, and the
+    // booking stays open until an insurance payout has been reviewed
+    pub(crate) fn has_open_claim(&self, booking_id: &str) -> bool {
+        self.damage_claims
+            .get(booking_id)
+            .is_some_and(|c| matches!(c.status, ClaimStatus::Pending | ClaimStatus::Contested) || c.pending_coverage > 0)
+    }
+}
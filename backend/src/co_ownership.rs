@@ -0,0 +1,246 @@
+// Co-owned cars: owners hold basis-point shares of a car and the payouts of a
+// booking are split between the co-owners of the time it was made. Changes to
+// a co-owned car are made by its designated manager, which every co-owned car
+// must have, or by a proposal approved by co-owners holding more than half of
+// the shares.
+use crate::*;
+use crate::disputes::MAX_SHARE_BPS;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct CoOwner {
+    pub account_id: String,
+    pub share_bps: u16,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub enum CarAction {
+    #[default]
+    DeleteCar,
+    SetHourlyRate(u128),
+    SetManager(Option<String>),
+    SetCoOwners(Vec<CoOwner>),
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct CarProposal {
+    pub proposal_id: String,
+    pub car_id: String,
+    pub action: CarAction,
+    pub proposer: String,
+    pub approvals: Vec<String>,
+    pub created_at: u64,
+    pub executed: bool,
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct CoOwnerEarnings {
+    pub account_id: String,
+    pub share_bps: u16,
+    pub earned: u128,
+}
+
+impl Car {
+    // co-owners of the car, a car without co-owners belongs entirely to its owner
+    pub fn shares(&self) -> Vec<CoOwner> {
+        if self.co_owners.is_empty() {
+            return vec![CoOwner {
+                account_id: self.owner_id.clone(),
+                share_bps: MAX_SHARE_BPS,
+            }];
+        }
+        self.co_owners.clone()
+    }
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // set_co_owners lets the sole owner of a car share it, once co-owned the
+    // shares can only change through a proposal
+    #[handle_result]
+    pub fn set_co_owners(&mut self, car_id: String, co_owners: Vec<CoOwner>, manager: Option<String>) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        if car.co_owners.len() > 1 {
+            return Err(Error::CarCoOwned);
+        }
+        validate_co_owners(&car.owner_id, &co_owners)?;
+        if let Some(manager) = manager.as_ref() {
+            manager.parse::<AccountId>().map_err(|_| Error::InvalidAccountId)?;
+        }
+        // without a manager nobody could run a co-owned car day to day
+        if co_owners.len() > 1 && manager.is_none() {
+            return Err(Error::ManagerRequired);
+        }
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.co_owners = co_owners.clone();
+        car.manager = manager.clone();
+        log!("Event: CoOwnersUpdated, car_id: {}, co_owners: {:?}, manager: {:?}", car_id, co_owners, manager);
+        Ok(format!("Co-owners of car '{}' updated", car_id))
+    }
+
+    // propose_car_change opens a vote among co-owners, the proposer approves it right away
+    #[handle_result]
+    pub fn propose_car_change(&mut self, car_id: String, action: CarAction) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        if !car.shares().iter().any(|c| c.account_id == caller) {
+            return Err(Error::Unauthorized);
+        }
+        self.next_proposal_id += 1;
+        let proposal_id: String = self.next_proposal_id.to_string();
+        self.car_proposals.insert(
+            proposal_id.clone(),
+            CarProposal {
+                proposal_id: proposal_id.clone(),
+                car_id: car_id.clone(),
+                action: action.clone(),
+                proposer: caller.clone(),
+                approvals: Vec::new(),
+                created_at: block_timestamp(),
+                executed: false,
+            },
+        );
+        log!("Event: CarChangeProposed, proposal_id: {}, car_id: {}, by: {}, action: {:?}", proposal_id, car_id, caller, action);
+        self.approve_car_change(proposal_id.clone())?;
+        Ok(format!("Proposal {} opened on car '{}'", proposal_id, car_id))
+    }
+
+    // approve_car_change records a co-owner's approval and applies the change
+    // once approvals hold a majority of the shares
+    #[handle_result]
+    pub fn approve_car_change(&mut self, proposal_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let proposal = self.car_proposals.get(&proposal_id).ok_or(Error::ProposalNotFound)?;
+        if proposal.executed {
+            return Err(Error::ProposalExecuted);
+        }
+        let car = self.cars.get(&proposal.car_id).ok_or(Error::CarNotFound)?;
+        let shares: Vec<CoOwner> = car.shares();
+        if !shares.iter().any(|c| c.account_id == caller) {
+            return Err(Error::Unauthorized);
+        }
+        let car_id: String = proposal.car_id.clone();
+        let action: CarAction = proposal.action.clone();
+        let proposal: &mut CarProposal = self.car_proposals.get_mut(&proposal_id).ok_or(Error::ProposalNotFound)?;
+        if !proposal.approvals.contains(&caller) {
+            proposal.approvals.push(caller.clone());
+        }
+        let approved_bps: u32 = shares
+            .iter()
+            .filter(|c| proposal.approvals.contains(&c.account_id))
+            .map(|c| c.share_bps as u32)
+            .sum();
+        log!("Event: CarChangeApproved, proposal_id: {}, by: {}, approved_bps: {}", proposal_id, caller, approved_bps);
+        if approved_bps * 2 <= MAX_SHARE_BPS as u32 {
+            return Ok(format!("Proposal {} approved by '{}'", proposal_id, caller));
+        }
+        proposal.executed = true;
+        self.apply_car_action(&car_id, action)?;
+        log!("Event: CarChangeExecuted, proposal_id: {}, car_id: {}", proposal_id, car_id);
+        Ok(format!("Proposal {} executed on car '{}'", proposal_id, car_id))
+    }
+
+    // read-only functions
+    #[handle_result]
+    pub fn get_co_owners(&self, car_id: String) -> Result<Vec<CoOwner>, String> {
+        self.cars.get(&car_id).map(|car| car.shares()).ok_or("Car not found".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_car_proposal(&self, proposal_id: String) -> Result<CarProposal, String> {
+        self.car_proposals.get(&proposal_id).cloned().ok_or("Proposal not found".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_car_earnings(&self, car_id: String) -> Result<Vec<CoOwnerEarnings>, String> {
+        let car = self.cars.get(&car_id).ok_or("Car not found".to_string())?;
+        Ok(car
+            .shares()
+            .into_iter()
+            .map(|c| CoOwnerEarnings {
+                earned: self.earnings.get(&(car_id.clone(), c.account_id.clone())).copied().unwrap_or(0),
+                account_id: c.account_id,
+                share_bps: c.share_bps,
+            })
+            .collect())
+    }
+
+    // total paid to an account across all the cars it owned or co-owned
+    pub fn get_owner_earnings(&self, account_id: String) -> u128 {
+        self.earnings.iter().filter(|((_, account), _)| *account == account_id).map(|(_, earned)| *earned).sum()
+    }
+}
+
+impl CarSharing {
+    // pay the owner side of a booking: the co-owners of the car when it was
+    // booked by share, the rounding remainder going to the owner of that time;
+    // as payees are fixed at booking time a later transfer, change of shares or
+    // removal of the car never redirects or drops a payout, and archived bookings
+    // can still be paid, for instance when the renter pays off a damage claim later
+    pub(crate) fn pay_booking_owners(&mut self, booking_id: &str, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        let booking = match self.bookings.get(booking_id).or_else(|| self.archived_bookings.get(booking_id)) {
+            Some(booking) => booking,
+            None => return,
+        };
+        let car_id: String = booking.car_id.clone();
+        let mut payouts: Vec<(String, u128)> = booking
+            .payees
+            .iter()
+            .map(|c| (c.account_id.clone(), amount * c.share_bps as u128 / MAX_SHARE_BPS as u128))
+            .collect();
+        let remainder: u128 = amount - payouts.iter().map(|(_, paid)| paid).sum::<u128>();
+        payouts.push((booking.owner_id.clone(), remainder));
+        for (account_id, paid) in payouts {
+            if paid == 0 {
+                continue;
+            }
+            *self.earnings.entry((car_id.clone(), account_id.clone())).or_default() += paid;
+            self.transfer(&account_id, paid);
+        }
+    }
+
+    fn apply_car_action(&mut self, car_id: &str, action: CarAction) -> Result<(), Error> {
+        match action {
+            CarAction::DeleteCar => self.remove_car(car_id)?,
+            CarAction::SetHourlyRate(hourly_rate) => self.set_hourly_rate(car_id, hourly_rate)?,
+            CarAction::SetManager(manager) => {
+                if let Some(manager) = manager.as_ref() {
+                    manager.parse::<AccountId>().map_err(|_| Error::InvalidAccountId)?;
+                }
+                let car: &mut Car = self.cars.get_mut(car_id).ok_or(Error::CarNotFound)?;
+                if car.co_owners.len() > 1 && manager.is_none() {
+                    return Err(Error::ManagerRequired);
+                }
+                car.manager = manager;
+            }
+            CarAction::SetCoOwners(co_owners) => {
+                let car: &mut Car = self.cars.get_mut(car_id).ok_or(Error::CarNotFound)?;
+                validate_co_owners(&car.owner_id, &co_owners)?;
+                if co_owners.len() > 1 && car.manager.is_none() {
+                    return Err(Error::ManagerRequired);
+                }
+                car.co_owners = co_owners;
+            }
+        }
+        Ok(())
+    }
+}
+
+// shares must be positive, unique per account, include the listing owner and add up to 100%
+fn validate_co_owners(owner_id: &str, co_owners: &[CoOwner]) -> Result<(), Error> {
+    let total: u32 = co_owners.iter().map(|c| c.share_bps as u32).sum();
+    if total != MAX_SHARE_BPS as u32 || co_owners.iter().any(|c| c.share_bps == 0) {
+        return Err(Error::InvalidShare);
+    }
+    if !co_owners.iter().any(|c| c.account_id == owner_id) {
+        return Err(Error::InvalidShare);
+    }
+    for (i, co_owner) in co_owners.iter().enumerate() {
+        co_owner.account_id.parse::<AccountId>().map_err(|_| Error::InvalidAccountId)?;
+        if co_owners[..i].iter().any(|c| c.account_id == co_owner.account_id) {
+            return Err(Error::InvalidShare);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,292 @@
+// Soulbound driver credentials: non-transferable tokens issued to users by
+// trusted verifiers, revocable and optionally expiring. Cars can require them
+// through their booking requirements, other contracts can read them through
+// the NEP-393 views below, where this contract is the issuer, a credential id is
+// the token id and its kind the class. Revoked credentials are kept and count
+// as expired.
+use crate::*;
+use near_sdk::env::current_account_id;
+
+pub const SBT_SPEC: &str = "sbt-1.0.0";
+
+pub type TokenId = u64;
+pub type ClassId = u64;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum CredentialKind {
+    #[default]
+    LicenseVerified,
+    AgeBracket, // value is the lower bound of the bracket, in years
+    YearsDriving, // value is the number of years
+    CleanRecord,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct Credential {
+    pub credential_id: String,
+    pub holder: String,
+    pub kind: CredentialKind,
+    pub value: u64,
+    pub issuer: String,
+    pub issued_at: u64,
+    pub expires_at: u64, // 0 when the credential never expires
+    pub revoked: bool,
+}
+impl Credential {
+    pub fn is_valid(&self, now: u64) -> bool {
+        !self.revoked && (self.expires_at == 0 || now < self.expires_at)
+    }
+
+    // the credential as a NEP-393 token, None if its id is not a token id
+    fn to_token(&self) -> Option<Token> {
+        Some(Token {
+            token: self.credential_id.parse().ok()?,
+            owner: self.holder.clone(),
+            metadata: TokenMetadata {
+                class: self.kind.class(),
+                issued_at: Some(self.issued_at / 1_000_000),
+                expires_at: (self.expires_at != 0).then(|| self.expires_at / 1_000_000),
+                reference: None,
+                reference_hash: None,
+            },
+        })
+    }
+}
+impl CredentialKind {
+    const ALL: [CredentialKind; 4] = [CredentialKind::LicenseVerified, CredentialKind::AgeBracket, CredentialKind::YearsDriving, CredentialKind::CleanRecord];
+
+    // NEP-393 class ids start at 1
+    pub fn class(&self) -> ClassId {
+        Self::ALL.iter().position(|kind| kind == self).unwrap_or(0) as ClassId + 1
+    }
+}
+
+// NEP-393 views
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ContractMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>, // base64
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct TokenMetadata {
+    pub class: ClassId,
+    pub issued_at: Option<u64>, // in ms
+    pub expires_at: Option<u64>, // in ms
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>, // base64
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Token {
+    pub token: TokenId,
+    pub owner: String,
+    pub metadata: TokenMetadata,
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct OwnedToken {
+    pub token: TokenId,
+    pub metadata: TokenMetadata,
+}
+
+// credential a car requires from its renters, `min_value` applies to valued kinds
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct CredentialRequirement {
+    pub kind: CredentialKind,
+    pub min_value: u64,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // issue_credential lets a verifier attest a fact about a registered user
+    #[handle_result]
+    pub fn issue_credential(&mut self, holder: String, kind: CredentialKind, value: u64, expires_at: u64) -> Result<String, Error> {
+        let caller: AccountId = predecessor_account_id();
+        if !self.verifiers.contains(&caller) {
+            return Err(Error::NotVerifier);
+        }
+        let holder_account: AccountId = holder.parse().map_err(|_| Error::InvalidAccountId)?;
+        if !self.is_user(&holder_account) {
+            return Err(Error::UserNotFound);
+        }
+        let now: u64 = block_timestamp();
+        if expires_at != 0 && expires_at <= now {
+            return Err(Error::InvalidCredential);
+        }
+        self.next_credential_id += 1;
+        let credential_id: String = self.next_credential_id.to_string();
+        self.credentials.insert(
+            credential_id.clone(),
+            Credential {
+                credential_id: credential_id.clone(),
+                holder: holder.clone(),
+                kind,
+                value,
+                issuer: caller.to_string(),
+                issued_at: now,
+                expires_at,
+                revoked: false,
+            },
+        );
+        log!("Event: CredentialIssued, credential_id: {}, holder: {}, kind: {:?}, value: {}, issuer: {}, expires_at: {}", credential_id, holder, kind, value, caller, expires_at);
+        Ok(format!("Credential {} issued to '{}'", credential_id, holder))
+    }
+
+    // revoke_credential can be called by the issuer or the contract admin
+    #[handle_result]
+    pub fn revoke_credential(&mut self, credential_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let is_admin: bool = caller == self.admin_id;
+        let credential: &mut Credential = self.credentials.get_mut(&credential_id).ok_or(Error::CredentialNotFound)?;
+        if credential.issuer != caller && !is_admin {
+            return Err(Error::Unauthorized);
+        }
+        credential.revoked = true;
+        log!("Event: CredentialRevoked, credential_id: {}, holder: {}, by: {}", credential_id, credential.holder, caller);
+        Ok(format!("Credential {} revoked", credential_id))
+    }
+
+    // verifier registry, managed by the contract admin
+    #[handle_result]
+    pub fn add_verifier(&mut self, account_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        let account_id: AccountId = account_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        if !self.verifiers.contains(&account_id) {
+            self.verifiers.push(account_id.clone());
+        }
+        log!("Event: VerifierAdded, account_id: {}", account_id);
+        Ok(format!("Verifier '{}' added", account_id))
+    }
+
+    #[handle_result]
+    pub fn remove_verifier(&mut self, account_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        let account_id: AccountId = account_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        self.verifiers.retain(|v| *v != account_id);
+        log!("Event: VerifierRemoved, account_id: {}", account_id);
+        Ok(format!("Verifier '{}' removed", account_id))
+    }
+
+    // set_required_credentials lets owners restrict a car to renters holding valid credentials
+    #[handle_result]
+    pub fn set_required_credentials(&mut self, car_id: String, required_credentials: Vec<CredentialRequirement>) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::ApproveBookings)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        log!("Event: RequiredCredentialsUpdated, car_id: {}, required_credentials: {:?}", car_id, required_credentials);
+        car.requirements.required_credentials = required_credentials;
+        Ok(format!("Required credentials of car '{}' updated", car_id))
+    }
+
+    // read-only functions
+    pub fn sbt_metadata(&self) -> ContractMetadata {
+        ContractMetadata {
+            spec: SBT_SPEC.to_string(),
+            name: "Car sharing driver credentials".to_string(),
+            symbol: "DRIVER".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    pub fn sbt(&self, ctr: AccountId, token: TokenId) -> Option<Token> {
+        if ctr != current_account_id() {
+            return None;
+        }
+        self.credentials.get(&token.to_string()).and_then(|c| c.to_token())
+    }
+
+    pub fn sbt_tokens(&self, issuer: AccountId, from_token: Option<TokenId>, limit: Option<u32>, with_expired: Option<bool>) -> Vec<Token> {
+        if issuer != current_account_id() {
+            return Vec::new();
+        }
+        let now: u64 = block_timestamp();
+        let with_expired: bool = with_expired.unwrap_or(false);
+        let from_token: TokenId = from_token.unwrap_or(0);
+        let mut tokens: Vec<Token> = self
+            .credentials
+            .values()
+            .filter(|c| with_expired || c.is_valid(now))
+            .filter_map(|c| c.to_token())
+            .filter(|t| t.token >= from_token)
+            .collect();
+        tokens.sort_by_key(|t| t.token);
+        tokens.truncate(limit.map(|limit| limit as usize).unwrap_or(usize::MAX));
+        tokens
+    }
+
+    // tokens held by the account, grouped by issuer and ordered by class then token
+    pub fn sbt_tokens_by_owner(&self, account: AccountId, issuer: Option<AccountId>, from_class: Option<ClassId>, limit: Option<u32>, with_expired: Option<bool>) -> Vec<(AccountId, Vec<OwnedToken>)> {
+        let contract_id: AccountId = current_account_id();
+        if issuer.is_some_and(|issuer| issuer != contract_id) {
+            return Vec::new();
+        }
+        let now: u64 = block_timestamp();
+        let with_expired: bool = with_expired.unwrap_or(false);
+        let from_class: ClassId = from_class.unwrap_or(0);
+        let mut tokens: Vec<Token> = self
+            .credentials
+            .values()
+            .filter(|c| c.holder == account.as_str() && c.kind.class() >= from_class && (with_expired || c.is_valid(now)))
+            .filter_map(|c| c.to_token())
+            .collect();
+        tokens.sort_by_key(|t| (t.metadata.class, t.token));
+        tokens.truncate(limit.map(|limit| limit as usize).unwrap_or(usize::MAX));
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+        vec![(contract_id, tokens.into_iter().map(|t| OwnedToken { token: t.token, metadata: t.metadata }).collect())]
+    }
+
+    // supplies include expired and revoked tokens, as the standard counts every token not burned
+    pub fn sbt_supply(&self, issuer: AccountId) -> u64 {
+        if issuer != current_account_id() {
+            return 0;
+        }
+        self.credentials.len() as u64
+    }
+
+    pub fn sbt_supply_by_owner(&self, account: AccountId, issuer: AccountId, class: Option<ClassId>) -> u64 {
+        if issuer != current_account_id() {
+            return 0;
+        }
+        self.credentials
+            .values()
+            .filter(|c| c.holder == account.as_str() && (class.is_none() || class == Some(c.kind.class())))
+            .count() as u64
+    }
+
+    pub fn sbt_supply_by_class(&self, issuer: AccountId, class: ClassId) -> u64 {
+        if issuer != current_account_id() {
+            return 0;
+        }
+        self.credentials.values().filter(|c| c.kind.class() == class).count() as u64
+    }
+
+    pub fn sbt_revoked(&self, issuer: AccountId, token: TokenId) -> bool {
+        issuer == current_account_id() && self.credentials.get(&token.to_string()).is_some_and(|c| c.revoked)
+    }
+
+    pub fn has_credential(&self, account_id: String, kind: CredentialKind, min_value: u64) -> bool {
+        self.holds_credential(&account_id, kind, min_value)
+    }
+
+    pub fn list_verifiers(&self) -> Vec<AccountId> {
+        self.verifiers.clone()
+    }
+}
+
+impl CarSharing {
+    // true when the account holds a valid credential of this kind with at least `min_value`
+    pub(crate) fn holds_credential(&self, account_id: &str, kind: CredentialKind, min_value: u64) -> bool {
+        let now: u64 = block_timestamp();
+        self.credentials
+            .values()
+            .any(|c| c.holder == account_id && c.kind == kind && c.value >= min_value && c.is_valid(now))
+    }
+}
@@ -0,0 +1,107 @@
+// Fleet manager delegation: owners grant scoped permissions on all their cars
+// to manager accounts and can revoke them at any time. Every owner-side
+// permission check of the contract goes through `authorize`.
+use crate::*;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum Permission {
+    #[default]
+    Owner, // held by the listing owner only, cannot be granted
+    AddCars,
+    RemoveCars,
+    SetRates, // rates, fees, deposits and return policies
+    ApproveBookings, // renter approvals, requirements and transfers
+    CheckInOut, // condition reports and no-shows
+}
+// what a permission is checked against: an owner account or one of its cars
+pub(crate) enum Scope<'a> {
+    Owner(&'a str),
+    Car(&'a Car),
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // grant_permissions adds permissions of a manager over all of the caller's cars
+    #[handle_result]
+    pub fn grant_permissions(&mut self, manager_id: String, permissions: Vec<Permission>) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        if !self.owners.contains_key(&caller) {
+            return Err(Error::OwnerNotFound);
+        }
+        manager_id.parse::<AccountId>().map_err(|_| Error::InvalidAccountId)?;
+        if manager_id == caller || permissions.contains(&Permission::Owner) {
+            return Err(Error::InvalidPermission);
+        }
+        let granted: &mut Vec<Permission> = self.delegations.entry((caller.clone(), manager_id.clone())).or_default();
+        for permission in permissions.iter() {
+            if !granted.contains(permission) {
+                granted.push(*permission);
+            }
+        }
+        log!("Event: PermissionsGranted, owner: {}, manager: {}, permissions: {:?}", caller, manager_id, permissions);
+        Ok(format!("Permissions granted to '{}'", manager_id))
+    }
+
+    // revoke_permissions removes the given permissions, or all of them when none are given
+    #[handle_result]
+    pub fn revoke_permissions(&mut self, manager_id: String, permissions: Option<Vec<Permission>>) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let key: (String, String) = (caller.clone(), manager_id.clone());
+        let granted: &mut Vec<Permission> = self.delegations.get_mut(&key).ok_or(Error::DelegationNotFound)?;
+        match permissions.as_ref() {
+            Some(permissions) => granted.retain(|p| !permissions.contains(p)),
+            None => granted.clear(),
+        }
+        if granted.is_empty() {
+            self.delegations.remove(&key);
+        }
+        log!("Event: PermissionsRevoked, owner: {}, manager: {}, permissions: {:?}", caller, manager_id, permissions);
+        Ok(format!("Permissions of '{}' revoked", manager_id))
+    }
+
+    // read-only functions
+    pub fn get_permissions(&self, owner_id: String, manager_id: String) -> Vec<Permission> {
+        self.delegations.get(&(owner_id, manager_id)).cloned().unwrap_or_default()
+    }
+
+    pub fn list_managers(&self, owner_id: String) -> Vec<(String, Vec<Permission>)> {
+        self.delegations
+            .iter()
+            .filter(|((owner, _), _)| *owner == owner_id)
+            .map(|((_, manager), permissions)| (manager.clone(), permissions.clone()))
+            .collect()
+    }
+}
+
+impl CarSharing {
+    // single authorization check for owner-side actions: the owner permission
+    // belongs to the listing owner, other permissions to whoever runs the car
+    // (its owner, or its manager when co-owned) and to the fleet managers they
+    // delegated it to
+    pub(crate) fn authorize(&self, caller: &str, scope: Scope, permission: Permission) -> Result<(), Error> {
+        let principals: Vec<&str> = match scope {
+            Scope::Owner(owner_id) => vec![owner_id],
+            Scope::Car(car) if permission == Permission::Owner => vec![car.owner_id.as_str()],
+            Scope::Car(car) => {
+                let mut principals: Vec<&str> = car.manager.as_deref().into_iter().collect();
+                if car.co_owners.len() <= 1 {
+                    principals.push(car.owner_id.as_str());
+                }
+                principals
+            }
+        };
+        if principals.contains(&caller) {
+            return Ok(());
+        }
+        let delegated: bool = permission != Permission::Owner
+            && principals.iter().any(|principal| {
+                self.delegations
+                    .get(&(principal.to_string(), caller.to_string()))
+                    .is_some_and(|granted| granted.contains(&permission))
+            });
+        if delegated {
+            return Ok(());
+        }
+        Err(Error::Unauthorized)
+    }
+}
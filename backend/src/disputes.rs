@@ -0,0 +1,254 @@
+// Dispute resolution between renters and owners, ruled by registered arbitrators.
+// Opening a dispute freezes the booking's escrow until a ruling or a default outcome.
+use crate::*;
+use crate::claims::ClaimStatus;
+
+pub const DISPUTE_EVIDENCE_PERIOD: u64 = 3 * 24 * pricing::NANOS_PER_HOUR; // 3 days
+pub const DISPUTE_RULING_PERIOD: u64 = 7 * 24 * pricing::NANOS_PER_HOUR; // 7 days
+pub const MAX_SHARE_BPS: u16 = 10_000;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum DisputeStatus {
+    #[default]
+    Open,
+    Resolved,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct Evidence {
+    pub submitted_by: String,
+    pub evidence_hash: String,
+    pub submitted_at: u64,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct Dispute {
+    pub booking_id: String,
+    pub opened_by: String,
+    pub reason: String,
+    pub evidence: Vec<Evidence>,
+    pub arbitrator: Option<String>,
+    pub opened_at: u64,
+    pub evidence_deadline: u64, // parties can submit evidence until then
+    pub ruling_deadline: u64, // the arbitrator should rule before then
+    pub status: DisputeStatus,
+    pub renter_share_bps: Option<u16>, // share of the escrow ruled for the renter
+    pub previous_status: BookingStatus,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // open_dispute lets the renter or the owner contest a reservation before it
+    // starts or a returned booking; its escrow is frozen and an arbitrator is
+    // assigned in turn from the registry
+    #[handle_result]
+    pub fn open_dispute(&mut self, booking_id: String, reason: String, evidence_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        self.booking_party(booking, &caller, Permission::Owner)?;
+        if booking.status == BookingStatus::Disputed {
+            return Err(Error::DisputeAlreadyOpen);
+        }
+        if booking.deposit == 0 && booking.security_deposit == 0 {
+            return Err(Error::NothingToDispute);
+        }
+        // a rental in progress must be settled by return_car first, which a
+        // disputed booking would escape, leaving the car unlisted
+        if booking.status.is_open() && block_timestamp() >= booking.start_time {
+            return Err(Error::RentalInProgress);
+        }
+        let previous_status: BookingStatus = booking.status;
+        let now: u64 = block_timestamp();
+        let arbitrator: Option<String> = if self.arbitrators.is_empty() {
+            None
+        } else {
+            let index: usize = (self.disputes_opened % self.arbitrators.len() as u64) as usize;
+            Some(self.arbitrators[index].to_string())
+        };
+        self.disputes_opened += 1;
+        self.disputes.insert(
+            booking_id.clone(),
+            Dispute {
+                booking_id: booking_id.clone(),
+                opened_by: caller.clone(),
+                reason: reason.clone(),
+                evidence: vec![Evidence {
+                    submitted_by: caller.clone(),
+                    evidence_hash,
+                    submitted_at: now,
+                }],
+                arbitrator: arbitrator.clone(),
+                opened_at: now,
+                evidence_deadline: now + DISPUTE_EVIDENCE_PERIOD,
+                ruling_deadline: now + DISPUTE_RULING_PERIOD,
+                status: DisputeStatus::Open,
+                renter_share_bps: None,
+                previous_status,
+            },
+        );
+        if let Some(booking) = self.bookings.get_mut(&booking_id) {
+            booking.status = BookingStatus::Disputed;
+        }
+        log!("Event: DisputeOpened, booking_id: {}, by: {}, reason: {}, arbitrator: {:?}", booking_id, caller, reason, arbitrator);
+        Ok(format!("Dispute opened on booking {}", booking_id))
+    }
+
+    // submit_evidence adds a hash of supporting documents until the evidence deadline
+    #[handle_result]
+    pub fn submit_evidence(&mut self, booking_id: String, evidence_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        self.booking_party(booking, &caller, Permission::Owner)?;
+        let now: u64 = block_timestamp();
+        let dispute: &mut Dispute = self.disputes.get_mut(&booking_id).ok_or(Error::DisputeNotFound)?;
+        if dispute.status != DisputeStatus::Open || now > dispute.evidence_deadline {
+            return Err(Error::DisputeClosed);
+        }
+        dispute.evidence.push(Evidence {
+            submitted_by: caller.clone(),
+            evidence_hash: evidence_hash.clone(),
+            submitted_at: now,
+        });
+        log!("Event: EvidenceSubmitted, booking_id: {}, by: {}, evidence_hash: {}", booking_id, caller, evidence_hash);
+        Ok(format!("Evidence added to dispute on booking {}", booking_id))
+    }
+
+    // resolve_dispute is the arbitrator's ruling: `renter_share_bps` of the
+    // escrow goes to the renter, the rest to the owner
+    #[handle_result]
+    pub fn resolve_dispute(&mut self, booking_id: String, renter_share_bps: u16) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let dispute = self.disputes.get(&booking_id).ok_or(Error::DisputeNotFound)?;
+        if dispute.arbitrator.as_deref() != Some(caller.as_str()) {
+            return Err(Error::Unauthorized);
+        }
+        if dispute.status != DisputeStatus::Open {
+            return Err(Error::DisputeClosed);
+        }
+        if renter_share_bps > MAX_SHARE_BPS {
+            return Err(Error::InvalidShare);
+        }
+        self.settle_dispute(&booking_id, renter_share_bps)?;
+        log!("Event: DisputeResolved, booking_id: {}, arbitrator: {}, renter_share_bps: {}", booking_id, caller, renter_share_bps);
+        Ok(format!("Dispute on booking {} resolved", booking_id))
+    }
+
+    // settle_dispute_by_default applies the default outcome once deadlines pass:
+    // if the other party never answered by the evidence deadline the opener
+    // wins, if no ruling came by the ruling deadline the escrow is split evenly
+    #[handle_result]
+    pub fn settle_dispute_by_default(&mut self, booking_id: String) -> Result<String, Error> {
+        let now: u64 = block_timestamp();
+        let dispute = self.disputes.get(&booking_id).ok_or(Error::DisputeNotFound)?;
+        if dispute.status != DisputeStatus::Open {
+            return Err(Error::DisputeClosed);
+        }
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        let respondent_answered: bool = dispute.evidence.iter().any(|e| e.submitted_by != dispute.opened_by);
+        let renter_share_bps: u16 = if now > dispute.evidence_deadline && !respondent_answered {
+            if dispute.opened_by == booking.user_id { MAX_SHARE_BPS } else { 0 }
+        } else if now > dispute.ruling_deadline {
+            MAX_SHARE_BPS / 2
+        } else {
+            return Err(Error::DisputeDeadlineNotReached);
+        };
+        self.settle_dispute(&booking_id, renter_share_bps)?;
+        log!("Event: DisputeDefaulted, booking_id: {}, renter_share_bps: {}", booking_id, renter_share_bps);
+        Ok(format!("Dispute on booking {} settled by default", booking_id))
+    }
+
+    // arbitrator registry, managed by the contract admin
+    #[handle_result]
+    pub fn add_arbitrator(&mut self, account_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        let account_id: AccountId = account_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        if !self.arbitrators.contains(&account_id) {
+            self.arbitrators.push(account_id.clone());
+        }
+        log!("Event: ArbitratorAdded, account_id: {}", account_id);
+        Ok(format!("Arbitrator '{}' added", account_id))
+    }
+
+    #[handle_result]
+    pub fn remove_arbitrator(&mut self, account_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        let account_id: AccountId = account_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        self.arbitrators.retain(|a| *a != account_id);
+        log!("Event: ArbitratorRemoved, account_id: {}", account_id);
+        Ok(format!("Arbitrator '{}' removed", account_id))
+    }
+
+    // assign_arbitrator lets the admin (re)assign an open dispute
+    #[handle_result]
+    pub fn assign_arbitrator(&mut self, booking_id: String, account_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        let arbitrator: AccountId = account_id.parse().map_err(|_| Error::InvalidAccountId)?;
+        if !self.arbitrators.contains(&arbitrator) {
+            return Err(Error::NotArbitrator);
+        }
+        let dispute: &mut Dispute = self.disputes.get_mut(&booking_id).ok_or(Error::DisputeNotFound)?;
+        if dispute.status != DisputeStatus::Open {
+            return Err(Error::DisputeClosed);
+        }
+        dispute.arbitrator = Some(account_id.clone());
+        log!("Event: ArbitratorAssigned, booking_id: {}, arbitrator: {}", booking_id, account_id);
+        Ok(format!("Arbitrator '{}' assigned to dispute on booking {}", account_id, booking_id))
+    }
+
+    // read-only functions
+    #[handle_result]
+    pub fn get_dispute(&self, booking_id: String) -> Result<Dispute, String> {
+        self.disputes.get(&booking_id).cloned().ok_or("Dispute not found".to_string())
+    }
+
+    pub fn list_arbitrator_disputes(&self, arbitrator: String) -> Vec<Dispute> {
+        self.disputes
+            .values()
+            .filter(|d| d.arbitrator.as_deref() == Some(arbitrator.as_str()) && d.status == DisputeStatus::Open)
+            .cloned()
+            .collect()
+    }
+}
+
+impl CarSharing {
+    pub(crate) fn assert_admin(&self) -> Result<(), Error> {
+        if predecessor_account_id().to_string() != self.admin_id {
+            return Err(Error::Unauthorized);
+        }
+        Ok(())
+    }
+
+    // split the frozen escrow of a disputed booking and close both the dispute and the booking
+    fn settle_dispute(&mut self, booking_id: &str, renter_share_bps: u16) -> Result<(), Error> {
+        let booking = self.bookings.get(booking_id).ok_or(Error::BookingNotFound)?;
+        let user_id: String = booking.user_id.clone();
+        let escrow: u128 = booking.deposit + booking.security_deposit;
+        let renter_share: u128 = escrow * renter_share_bps as u128 / MAX_SHARE_BPS as u128;
+        let owner_share: u128 = escrow - renter_share;
+        // on insured bookings the pool covers the damage awarded on a contested
+        // claim above the deductible, once the payout is approved; until then the
+        // renter keeps that part of the escrow
+        let covered: u128 = match self.damage_claims.get(booking_id) {
+            Some(claim) if claim.status == ClaimStatus::Contested => self.insurance_coverage(booking, owner_share.min(claim.amount)),
+            _ => 0,
+        };
+        if let Some(dispute) = self.disputes.get_mut(booking_id) {
+            dispute.status = DisputeStatus::Resolved;
+            dispute.renter_share_bps = Some(renter_share_bps);
+        }
+        if let Some(claim) = self.damage_claims.get_mut(booking_id) {
+            claim.status = ClaimStatus::Closed;
+            claim.pending_coverage = covered;
+        }
+        if let Some(booking) = self.bookings.get_mut(booking_id) {
+            booking.deposit = 0;
+            booking.security_deposit = 0;
+            booking.status = BookingStatus::Completed;
+        }
+        self.transfer(&user_id, renter_share + covered);
+        self.pay_booking_owners(booking_id, owner_share - covered);
+        if covered > 0 {
+            log!("Event: InsurancePayoutRequested, booking_id: {}, amount: {}", booking_id, covered);
+        }
+        log!("Event: DisputeSettled, booking_id: {}, renter_share: {}, owner_share: {}", booking_id, renter_share, owner_share);
+        Ok(())
+    }
+}
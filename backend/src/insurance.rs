@@ -0,0 +1,160 @@
+// Community insurance: renters pick a tier when booking and pay its premium on
+// top of the rental price into an on-contract pool, which covers damage claims
+// above the tier's deductible once an arbitrator or the admin approves the payout.
+use crate::*;
+use crate::claims::ClaimStatus;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct InsuranceTier {
+    pub tier_id: String,
+    pub premium_bps: u16, // premium as a share of the rental fee, in basis points
+    pub deductible: u128, // part of each claim left to the renter
+    pub max_coverage: u128, // most the pool pays for one claim
+}
+#[derive(Default, Serialize, Deserialize, JsonSchema)]
+pub struct PoolStatus {
+    pub balance: u128,
+    pub premiums_collected: u128,
+    pub claims_paid: u128,
+    pub active_coverage: u128, // maximum exposure of the pool to bookings still open to claims
+    pub solvency_bps: u64, // balance over active coverage in basis points, 10000 means fully covered
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // review_insurance_payout lets an arbitrator or the admin approve the covered
+    // part of a damage claim, paid from the pool, or reject it, leaving it to the
+    // renter as a debt; what the pool cannot pay is left to the renter as well
+    #[handle_result]
+    pub fn review_insurance_payout(&mut self, booking_id: String, approve: bool) -> Result<String, Error> {
+        let caller: AccountId = predecessor_account_id();
+        if caller.as_str() != self.admin_id && !self.arbitrators.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        let claim = self.damage_claims.get(&booking_id).ok_or(Error::DamageClaimNotFound)?;
+        if claim.pending_coverage == 0 || !matches!(claim.status, ClaimStatus::Accepted | ClaimStatus::Closed) {
+            return Err(Error::InvalidClaimStatus);
+        }
+        let requested: u128 = claim.pending_coverage;
+        let user_id: String = claim.user_id.clone();
+        let payout: u128 = if approve { requested.min(self.insurance_pool) } else { 0 };
+        let unpaid: u128 = requested - payout;
+        self.insurance_pool -= payout;
+        self.insurance_claims_paid += payout;
+        if let Some(claim) = self.damage_claims.get_mut(&booking_id) {
+            claim.pending_coverage = 0;
+            claim.outstanding += unpaid;
+        }
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.debt += unpaid;
+        }
+        self.pay_booking_owners(&booking_id, payout);
+        log!("Event: InsurancePayoutReviewed, booking_id: {}, by: {}, approved: {}, payout: {}, debt: {}", booking_id, caller, approve, payout, unpaid);
+        Ok(format!("Insurance payout of {} on booking {} reviewed", payout, booking_id))
+    }
+
+    // fund_insurance_pool lets anyone add funds to the pool
+    #[payable]
+    pub fn fund_insurance_pool(&mut self) -> String {
+        let amount: u128 = attached_deposit().as_yoctonear();
+        self.insurance_pool += amount;
+        log!("Event: InsurancePoolFunded, by: {}, amount: {}", predecessor_account_id(), amount);
+        format!("Insurance pool funded with {}", amount)
+    }
+
+    // coverage tiers, managed by the contract admin
+    #[handle_result]
+    pub fn set_insurance_tier(&mut self, tier_id: String, premium_bps: u16, deductible: u128, max_coverage: u128) -> Result<String, Error> {
+        self.assert_admin()?;
+        if premium_bps > 10_000 {
+            return Err(Error::InvalidShare);
+        }
+        self.insurance_tiers.insert(
+            tier_id.clone(),
+            InsuranceTier {
+                tier_id: tier_id.clone(),
+                premium_bps,
+                deductible,
+                max_coverage,
+            },
+        );
+        log!("Event: InsuranceTierUpdated, tier: {}, premium_bps: {}, deductible: {}, max_coverage: {}", tier_id, premium_bps, deductible, max_coverage);
+        Ok(format!("Insurance tier '{}' saved", tier_id))
+    }
+
+    #[handle_result]
+    pub fn remove_insurance_tier(&mut self, tier_id: String) -> Result<String, Error> {
+        self.assert_admin()?;
+        self.insurance_tiers.remove(&tier_id).ok_or(Error::InsuranceTierNotFound)?;
+        log!("Event: InsuranceTierRemoved, tier: {}", tier_id);
+        Ok(format!("Insurance tier '{}' removed", tier_id))
+    }
+
+    // read-only functions
+    pub fn list_insurance_tiers(&self) -> Vec<InsuranceTier> {
+        self.insurance_tiers.values().cloned().collect()
+    }
+
+    pub fn get_insurance_pool(&self) -> PoolStatus {
+        // insured bookings that can still lead to a covered damage claim
+        let now: u64 = block_timestamp();
+        let active_coverage: u128 = self
+            .bookings
+            .values()
+            .filter(|b| {
+                let claim_possible = b.status.is_blocking() || b.claim_window_open(now);
+                match self.damage_claims.get(&b.booking_id) {
+                    Some(_) => self.has_open_claim(&b.booking_id),
+                    None => claim_possible,
+                }
+            })
+            .filter_map(|b| b.insurance.as_ref())
+            .map(|tier| tier.max_coverage)
+            .sum();
+        let solvency_bps: u64 = if active_coverage == 0 {
+            10_000
+        } else {
+            (self.insurance_pool * 10_000 / active_coverage).min(u64::MAX as u128) as u64
+        };
+        PoolStatus {
+            balance: self.insurance_pool,
+            premiums_collected: self.premiums_collected,
+            claims_paid: self.insurance_claims_paid,
+            active_coverage,
+            solvency_bps,
+        }
+    }
+}
+
+impl CarSharing {
+    // tier picked when booking and its premium on the rental fee
+    pub(crate) fn insurance_quote(&self, tier_id: Option<String>, rental_fee: u128) -> Result<(Option<InsuranceTier>, u128), Error> {
+        match tier_id {
+            Some(tier_id) => {
+                let tier: InsuranceTier = self.insurance_tiers.get(&tier_id).cloned().ok_or(Error::InsuranceTierNotFound)?;
+                let premium: u128 = rental_fee * tier.premium_bps as u128 / 10_000;
+                Ok((Some(tier), premium))
+            }
+            None => Ok((None, 0)),
+        }
+    }
+
+    // premiums go to the pool as soon as the booking is made
+    pub(crate) fn collect_premium(&mut self, booking_id: &str, premium: u128) {
+        if premium == 0 {
+            return;
+        }
+        self.insurance_pool += premium;
+        self.premiums_collected += premium;
+        log!("Event: BookingInsured, booking_id: {}, premium: {}", booking_id, premium);
+    }
+
+    // part of a damage claim the pool covers for an insured booking, limited by
+    // the tier's deductible and maximum coverage and by the pool balance
+    pub(crate) fn insurance_coverage(&self, booking: &Booking, amount: u128) -> u128 {
+        match booking.insurance.as_ref() {
+            Some(tier) => amount.saturating_sub(tier.deductible).min(tier.max_coverage).min(self.insurance_pool),
+            None => 0,
+        }
+    }
+}
@@ -3,23 +3,56 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use near_sdk::env::{attached_deposit, block_timestamp, predecessor_account_id};
-use near_sdk::{AccountId};
+use near_sdk::{AccountId, Promise};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{near_bindgen};
 use near_sdk::log;
 use near_sdk::FunctionError;
 use near_token::NearToken;
 
+pub mod access;
+pub mod batch;
+pub mod booking_tokens;
+pub mod claims;
+pub mod co_ownership;
+pub mod credentials;
+pub mod delegation;
+pub mod disputes;
+pub mod insurance;
+pub mod maintenance;
+pub mod nft;
+pub mod pricing;
+pub mod requirements;
+pub mod reviews;
+pub mod telematics;
+use access::AccessGrant;
+use claims::DamageClaim;
+use co_ownership::{CarProposal, CoOwner};
+use credentials::Credential;
+use delegation::{Permission, Scope};
+use disputes::Dispute;
+use insurance::InsuranceTier;
+use maintenance::{MaintenanceWindow, ServiceEntry};
+use pricing::{EarlyReturnPolicy, PriceSnapshot};
+use requirements::BookingRequirements;
+use reviews::{Rating, Review};
+use telematics::{TelematicsDevice, TelemetryReading};
+
 #[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
 pub struct User {
     pub user_id: String,
     pub name: String,
     driving_license: String,
+    pub rating: Rating,
+    pub completed_rentals: u64,
+    pub license_issued_at: u64,
+    pub debt: u128, // unpaid damage claims and booking balances, blocks new bookings
 }
 #[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
 pub struct Owner {
     pub owner_id: String,
     pub name: String,
+    pub rating: Rating,
 }
 #[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
 pub struct Car {
@@ -27,8 +60,41 @@ pub struct Car {
     pub owner_id: String,
     pub available: bool,
     pub hourly_rate: u128,
+    pub late_fee_rate: u128, // per started hour after end_time, falls back to hourly_rate when 0
+    pub grace_period: u64, // in nanoseconds
+    pub early_return_policy: EarlyReturnPolicy,
+    pub no_show_grace: u64, // in nanoseconds after start_time
+    pub no_show_fee: u128,
+    pub rating: Rating,
+    pub requirements: BookingRequirements,
+    pub security_deposit: u128, // held on top of the rental payment
+    pub security_deposit_hold_hours: u64, // how long after return the security deposit is kept
+    pub co_owners: Vec<CoOwner>, // shares of the car, empty when the owner holds it alone
+    pub manager: Option<String>, // runs the car on behalf of its co-owners
+    pub booking_transfers_disabled: bool, // renters cannot hand their reservations to another user
+    pub listed_at: u64,
+    pub service_interval_km: u64, // distance between services, 0 to disable
+    pub service_interval: u64, // time between services in nanoseconds, 0 to disable
+    pub lock_device_id: Option<String>, // smart lock opened with access grants
+    pub lock_authority_key: Option<String>, // ed25519 key the lock trusts, access grants are signed with it
+    pub mileage_rate: u128, // per km driven, 0 when mileage is not billed
+    pub return_area: Option<String>, // geohash prefix where the car must be returned
     // add vehicle licence or registration certificate (carte grise)
 }
+impl Car {
+    // current pricing terms, captured into every new booking
+    pub fn price_snapshot(&self) -> PriceSnapshot {
+        PriceSnapshot {
+            hourly_rate: self.hourly_rate,
+            late_fee_rate: if self.late_fee_rate == 0 { self.hourly_rate } else { self.late_fee_rate },
+            grace_period: self.grace_period,
+            early_return_policy: self.early_return_policy,
+            no_show_grace: self.no_show_grace,
+            no_show_fee: self.no_show_fee,
+            mileage_rate: self.mileage_rate,
+        }
+    }
+}
 #[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
 pub struct Booking {
     pub booking_id: String,
@@ -36,7 +102,80 @@ pub struct Booking {
     pub user_id: String,
     pub start_time: u64,
     pub end_time: u64,
-    pub deposit: u128,
+    pub deposit: u128, // rental payment held in escrow: the reservation deposit or the full payment
+    pub rental_fee: u128, // agreed price for the booked period
+    pub pricing: PriceSnapshot, // pricing terms of the car when the booking was made
+    pub security_deposit: u128, // held separately, released after return unless claimed
+    pub security_deposit_release_at: u64,
+    pub fees: u128, // overtime, mileage and no-show fees charged on top of the rental fee
+    pub amount_due: u128, // left to pay by the renter after settlement
+    pub returned_at: Option<u64>, // effective end time of the rental, before or after end_time
+    pub status: BookingStatus,
+    pub check_in: Option<ConditionReport>,
+    pub check_out: Option<ConditionReport>,
+    pub history: Vec<BookingChange>,
+    pub renter_reviewed: bool,
+    pub owner_reviewed: bool,
+    pub insurance: Option<InsuranceTier>, // coverage chosen when booking
+    pub insurance_premium: u128,
+    pub token_minted: bool, // the reservation can be transferred to another user
+    pub owner_id: String, // owner of the car when it was booked
+    pub payees: Vec<CoOwner>, // shares of the car when it was booked, payouts are split between them
+    pub start_odometer: Option<u64>, // telematics reading when the trip started
+    pub distance: u64, // km billed at return
+    pub cleanup_fee: u128, // paid to whoever archives the booking once it is settled
+}
+impl Booking {
+    // the renter signed the check-in report, the car was handed over
+    pub fn picked_up(&self) -> bool {
+        self.check_in.as_ref().is_some_and(|report| report.renter_signed)
+    }
+}
+// previous terms of a booking, kept when it is extended or modified
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct BookingChange {
+    pub changed_at: u64,
+    pub car_id: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub rental_fee: u128,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum BookingStatus {
+    #[default]
+    Reserved,
+    Active,
+    Completed,
+    NoShow,
+    Disputed,
+}
+impl BookingStatus {
+    // reserved and ongoing bookings can still be changed by the renter
+    pub fn is_open(&self) -> bool {
+        matches!(self, BookingStatus::Reserved | BookingStatus::Active)
+    }
+
+    // open and disputed bookings keep the car unavailable for their period
+    pub fn is_blocking(&self) -> bool {
+        self.is_open() || *self == BookingStatus::Disputed
+    }
+}
+// condition of the car at pickup or return, signed by both renter and owner
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct ConditionReport {
+    pub odometer: u64, // in km
+    pub fuel_level: u8, // fuel or battery level in percent
+    pub photos_hash: String,
+    pub reported_at: u64,
+    pub renter_signed: bool,
+    pub owner_signed: bool,
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct TripSummary {
+    pub booking_id: String,
+    pub distance: u64,
+    pub fuel_used: i16, // negative when the car came back with more fuel
+    pub fully_signed: bool,
 }
 
 #[near_bindgen]
@@ -48,13 +187,47 @@ pub struct CarSharing {
     pub bookings: HashMap<String, Booking>,
     pub users_accounts: Vec<AccountId>,
     pub owners_accounts: Vec<AccountId>,
+    pub archived_bookings: HashMap<String, Booking>,
+    pub expired_cursor: u64, // last booking id examined by process_expired
+    pub next_booking_id: u64,
+    pub booking_keys: HashMap<(String, String, u64), String>, // (car_id, user_id, start_time) -> booking id
+    pub admin_id: String,
+    pub arbitrators: Vec<AccountId>,
+    pub disputes: HashMap<String, Dispute>, // keyed by booking id
+    pub disputes_opened: u64,
+    pub car_reviews: HashMap<String, Vec<Review>>, // keyed by car id
+    pub user_reviews: HashMap<String, Vec<Review>>, // keyed by user id
+    pub damage_claims: HashMap<String, DamageClaim>, // keyed by booking id
+    pub insurance_tiers: HashMap<String, InsuranceTier>,
+    pub insurance_pool: u128,
+    pub premiums_collected: u128,
+    pub insurance_claims_paid: u128,
+    pub verifiers: Vec<AccountId>,
+    pub credentials: HashMap<String, Credential>, // keyed by credential id
+    pub next_credential_id: u64,
+    pub car_proposals: HashMap<String, CarProposal>, // keyed by proposal id
+    pub next_proposal_id: u64,
+    pub earnings: HashMap<(String, String), u128>, // (car_id, account) -> total paid out
+    pub delegations: HashMap<(String, String), Vec<Permission>>, // (owner, manager) -> granted permissions
+    pub maintenance_windows: HashMap<String, Vec<MaintenanceWindow>>, // keyed by car id
+    pub service_logs: HashMap<String, Vec<ServiceEntry>>, // keyed by car id
+    pub access_grants: HashMap<String, AccessGrant>, // keyed by booking id
+    pub telematics_devices: HashMap<String, Vec<TelematicsDevice>>, // keyed by car id
+    pub telemetry: HashMap<String, TelemetryReading>, // latest reading, keyed by car id
+    pub cleanup_fee: u128, // charged on every new booking, set by the admin
 }
 
+pub const MAX_CLEANUP_BATCH: u32 = 100;
+
 #[near_bindgen]
 impl CarSharing {
     #[init]
     pub fn init() -> Self {
-        Self::default() //initialized the contract with all fields in structure set to default values
+        //initialized the contract with all fields in structure set to default values, the deployer is admin
+        Self {
+            admin_id: predecessor_account_id().to_string(),
+            ..Self::default()
+        }
     }
     #[handle_result]
     pub fn create_owner_account(&mut self, owner_id: String, name: String) -> Result<String, Error> {
@@ -68,6 +241,7 @@ impl CarSharing {
             Owner {
                 owner_id: owner_id.clone(),
                 name,
+                ..Default::default()
             },
         );
         self.owners_accounts.push(account_id);
@@ -89,6 +263,7 @@ impl CarSharing {
                 user_id: user_id.clone(),
                 name,
                 driving_license,
+                ..Default::default()
             },
         );
         self.users_accounts.push(account_id);
@@ -98,31 +273,9 @@ impl CarSharing {
 
     #[handle_result]
     pub fn add_car(&mut self, car_id: String, owner_id: String, hourly_rate: u128) -> Result<String, Error> {
-        // Ensure caller has permission to add a car
-        let caller: AccountId = predecessor_account_id();
-        if !self.is_owner(&caller) {
-            return Err(Error::Unauthorized);
-        }
-        if self.cars.contains_key(&car_id) {
-            return Err(Error::CarAlreadyExists);
-        }
-        if !self.owners.contains_key(&owner_id) {
-            return Err(Error::OwnerNotFound);
-        }
-        // Validate the hourly rate to prevent invalid inputs
-        if hourly_rate == 0 {
-            return Err(Error::InvalidHourlyRate);
-        }
-        self.cars.insert(
-            car_id.clone(),
-            Car {
-                car_id: car_id.clone(),
-                owner_id: owner_id.clone(),
-                available: true,
-                hourly_rate,
-            },
-        );
-        log!("Event: CarAdded, car_id: {}, owner: {}", car_id.clone(), owner_id.clone());
+        let caller = predecessor_account_id().to_string();
+        self.check_new_car(&caller, &car_id, &owner_id, hourly_rate)?;
+        self.insert_car(&car_id, &owner_id, hourly_rate);
         Ok(format!("Car '{}' added successfully with owner '{}'", car_id, owner_id))
     }
 
@@ -133,20 +286,60 @@ impl CarSharing {
         let caller = predecessor_account_id().to_string();
         // retrieve the car to check its ownership
         let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
-        // Ensure the caller is the owner of the car
-        if car.owner_id != caller {
-            return Err(Error::Unauthorized);
-        }
-        // remove the car from the mapping
-        self.cars.remove(&car_id);
-        log!("Event: Car deleted, car_id: {}", car_id);
+        // Ensure the caller runs the car
+        self.authorize(&caller, Scope::Car(car), Permission::RemoveCars)?;
+        self.remove_car(&car_id)?;
         Ok(format!("Car {} deleted successfully.", car_id))
     }
 
-    // book_car allows users to book a car in advance with a deposit
+    // force_delete_car lets owners delete a car that still has bookings:
+    // every affected renter is refunded in full, and any attached deposit is
+    // split evenly between them as a penalty paid by the owner
+    #[payable]
+    #[handle_result]
+    pub fn force_delete_car(&mut self, car_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::RemoveCars)?;
+        let booking_ids: Vec<String> = self.car_booking_ids(&car_id);
+        // disputed escrow stays frozen until the dispute is settled
+        if booking_ids.iter().any(|id| self.bookings.get(id).is_some_and(|b| b.status == BookingStatus::Disputed)) {
+            return Err(Error::DisputeAlreadyOpen);
+        }
+        let penalty_pool: u128 = attached_deposit().as_yoctonear();
+        let penalty_per_booking: u128 = if booking_ids.is_empty() {
+            0
+        } else {
+            penalty_pool / booking_ids.len() as u128
+        };
+        for booking_id in booking_ids.iter() {
+            if let Some(booking) = self.remove_booking(booking_id) {
+                // a balance left due on the removed booking can no longer be paid
+                if let Some(user) = self.users.get_mut(&booking.user_id) {
+                    user.debt = user.debt.saturating_sub(booking.amount_due);
+                }
+                // the premium of a booking that never happens goes back to the renter
+                let premium_refund: u128 = booking.insurance_premium.min(self.insurance_pool);
+                self.insurance_pool -= premium_refund;
+                self.premiums_collected = self.premiums_collected.saturating_sub(premium_refund);
+                let refund: u128 = booking.deposit + booking.security_deposit + booking.cleanup_fee + premium_refund + penalty_per_booking;
+                self.transfer(&booking.user_id, refund);
+                log!("Event: BookingCancelledByOwner, booking_id: {}, user: {}, refund: {}, penalty: {}", booking_id, booking.user_id, refund - penalty_per_booking, penalty_per_booking);
+            }
+        }
+        // return whatever could not be split evenly to the owner
+        let leftover: u128 = penalty_pool - penalty_per_booking * booking_ids.len() as u128;
+        self.transfer(&caller, leftover);
+        self.remove_car(&car_id)?;
+        Ok(format!("Car {} deleted successfully, {} bookings cancelled.", car_id, booking_ids.len()))
+    }
+
+    // book_car allows users to book a car in advance, the reservation deposit,
+    // the car's security deposit and the premium of the chosen insurance tier
+    // are attached to the call
     #[payable]
     #[handle_result]
-    pub fn book_car(&mut self, car_id: String, user_id: String, start_time: u64, end_time: u64, deposit: NearToken) -> Result<String, Error> {
+    pub fn book_car(&mut self, car_id: String, user_id: String, start_time: u64, end_time: u64, insurance_tier: Option<String>) -> Result<String, Error> {
         // Convert user_id to AccountId
         let user_account_id: AccountId = user_id.parse().map_err(|_| Error::InvalidAccountId)?;
         // Ensure the driver is valid, the car exists, and is available
@@ -156,7 +349,7 @@ impl CarSharing {
         if !self.cars.contains_key(&car_id) {
             return Err(Error::CarNotFound);
         }
-        if start_time >= end_time {
+        if start_time >= end_time || start_time < block_timestamp() {
             return Err(Error::InvalidBookingTime);
         }
         // ensure the car exists and is available
@@ -164,25 +357,29 @@ impl CarSharing {
         if !car.available {
             return Err(Error::CarNotAvailable);
         }
+        // Ensure the driver meets the owner's requirements for this car
+        self.check_requirements(car, &user_id)?;
         // Ensure the car is not already booked for this period
-        if self.bookings.values().any(|booking| {
-            booking.car_id == car_id
-                && ((start_time >= booking.start_time && start_time < booking.end_time)
-                || (end_time > booking.start_time && end_time <= booking.end_time)
-                || (start_time <= booking.start_time && end_time >= booking.end_time))
-        }) {
+        if self.has_overlapping_booking(&car_id, start_time, end_time, None) {
             return Err(Error::CarNotAvailable);
         }
         // Calculate deposit required (10% of rental fee)
-        let rental_duration: u64 = (end_time - start_time) / 3600000000000; // Convert to hours
-        let rental_fee: u128 = (rental_duration as u128) * car.hourly_rate;
-        let deposit_amount: NearToken = NearToken::from_yoctonear((rental_fee / 10) * 9); // 10% of rental fee
-        // Check if enough deposit was attached
-        if deposit < deposit_amount {
+        let terms: PriceSnapshot = car.price_snapshot();
+        let rental_fee: u128 = pricing::rental_fee(terms.hourly_rate, start_time, end_time);
+        let deposit_amount: u128 = (rental_fee / 10) * 9; // 10% of rental fee
+        // Check that the reservation deposit, the security deposit held apart
+        // from it, the cleanup fee and the insurance premium were all attached
+        let security_deposit: u128 = car.security_deposit;
+        let (owner_id, payees): (String, Vec<CoOwner>) = (car.owner_id.clone(), car.shares());
+        let cleanup_fee: u128 = self.cleanup_fee;
+        let (insurance, insurance_premium) = self.insurance_quote(insurance_tier, rental_fee)?;
+        let attached: u128 = attached_deposit().as_yoctonear();
+        if attached < deposit_amount + security_deposit + cleanup_fee + insurance_premium {
             return Err(Error::InsufficientDeposit);
         }
+        let deposit: u128 = attached - security_deposit - cleanup_fee - insurance_premium;
         // Generate a unique booking ID
-        let booking_id: String = format!("{}-{}-{}", car_id, user_id, start_time);
+        let booking_id: String = self.new_booking_id(&car_id, &user_id, start_time);
         // Create booking
         self.bookings.insert(
             booking_id.clone(),
@@ -192,33 +389,53 @@ impl CarSharing {
                 user_id: user_id.clone(),
                 start_time,
                 end_time,
-                deposit: deposit.as_yoctonear(),
+                deposit,
+                rental_fee,
+                pricing: terms,
+                security_deposit,
+                status: BookingStatus::Reserved,
+                cleanup_fee,
+                insurance,
+                insurance_premium,
+                owner_id,
+                payees,
+                ..Default::default()
             },
         );
+        self.collect_premium(&booking_id, insurance_premium);
         // Emit event
-        log!("Event: CarBooked, car_id: {}, user: {}, start_time: {}, end_time: {}, deposit: {}", car_id, user_id.clone(), start_time, end_time, deposit_amount.as_yoctonear());
+        log!("Event: CarBooked, car_id: {}, user: {}, start_time: {}, end_time: {}, deposit: {}", car_id, user_id.clone(), start_time, end_time, deposit);
         Ok(format!("Car '{}' booked successfully from {} to {} by '{}'", car_id, start_time, end_time, user_id))
     }
 
+    // cancel_booking lets the renter drop a reservation that has not started yet
     #[payable]
     #[handle_result]
     pub fn cancel_booking(&mut self, booking_id: String) -> Result<String, Error> {
-        if let Some(booking) = self.bookings.remove(&booking_id) {
-            let _car_id: String = booking.car_id.clone();
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if booking.status != BookingStatus::Reserved {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if let Some(booking) = self.remove_booking(&booking_id) {
             let user_id: String = booking.user_id.clone();
             let deposit: u128 = booking.deposit;
-            // No refund is processed: the 10% deposit is retained
+            // No refund is processed: the 10% deposit is retained, only the security
+            // deposit and the unused cleanup fee are given back
+            self.transfer(&user_id, booking.security_deposit + booking.cleanup_fee);
             log!("Event: BookingCancelled, booking_id: {}, user: {}, deposit_retained: {}", booking_id.clone(), user_id.clone(), deposit);
-            Ok(format!("Booking {} cancelled successfully.", booking_id))
-        } else {
-            Err(Error::BookingNotFound)
         }
+        Ok(format!("Booking {} cancelled successfully.", booking_id))
     }
 
-    // rent_car allows users to rent a car immediately with payment
+    // rent_car allows users to rent a car immediately with payment, optionally
+    // insured with the chosen tier
     #[payable]
     #[handle_result]
-    pub fn rent_car(&mut self, car_id: String, user_id: String, duration: u32) -> Result<String, Error> {
+    pub fn rent_car(&mut self, car_id: String, user_id: String, duration: u32, insurance_tier: Option<String>) -> Result<String, Error> {
         // Convert user_id to AccountId
         let user_account_id: AccountId = user_id.parse().map_err(|_| Error::InvalidAccountId)?;
         
@@ -232,24 +449,26 @@ impl CarSharing {
         
         // Ensure the car exists and is available on the requested period
         let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
-        if self.bookings.values().any(|booking| {
-            booking.car_id == car_id
-                && ((start_time >= booking.start_time && start_time < booking.end_time)
-                || (end_time > booking.start_time && end_time <= booking.end_time)
-                || (start_time <= booking.start_time && end_time >= booking.end_time))
-        }) {
+        if self.has_overlapping_booking(&car_id, start_time, end_time, None) {
             return Err(Error::CarNotAvailable);
         }
+        self.check_requirements(car, &user_id)?;
 
-        // Ensure required payment is attached
-        let required_payment: NearToken = NearToken::from_yoctonear((duration as u128) * car.hourly_rate);
+        // Ensure required payment and security deposit are attached
+        let terms: PriceSnapshot = car.price_snapshot();
+        let required_payment: NearToken = NearToken::from_yoctonear((duration as u128) * terms.hourly_rate);
+        let security_deposit: u128 = car.security_deposit;
+        let (owner_id, payees): (String, Vec<CoOwner>) = (car.owner_id.clone(), car.shares());
+        let cleanup_fee: u128 = self.cleanup_fee;
+        let (insurance, insurance_premium) = self.insurance_quote(insurance_tier, required_payment.as_yoctonear())?;
         let attached_payment: NearToken = attached_deposit().into();
-        if attached_payment < required_payment {
+        if attached_payment.as_yoctonear() < required_payment.as_yoctonear() + security_deposit + cleanup_fee + insurance_premium {
             return Err(Error::InsufficientPayment);
         }
+        let start_odometer: Option<u64> = self.telemetry.get(&car_id).map(|reading| reading.odometer);
 
         // Generate a unique booking ID and create the booking
-        let booking_id: String = format!("{}-{}-{}", car_id, user_id, start_time);
+        let booking_id: String = self.new_booking_id(&car_id, &user_id, start_time);
         self.bookings.insert(
             booking_id.clone(),
             Booking {
@@ -258,9 +477,21 @@ impl CarSharing {
                 user_id: user_id.clone(),
                 start_time,
                 end_time,
-                deposit: attached_payment.as_yoctonear(),
+                deposit: attached_payment.as_yoctonear() - security_deposit - cleanup_fee - insurance_premium,
+                rental_fee: required_payment.as_yoctonear(),
+                pricing: terms,
+                security_deposit,
+                status: BookingStatus::Active,
+                start_odometer,
+                cleanup_fee,
+                insurance,
+                insurance_premium,
+                owner_id,
+                payees,
+                ..Default::default()
             },
         );
+        self.collect_premium(&booking_id, insurance_premium);
         
         // Mark car as unavailable
         if let Some(car) = self.cars.get_mut(&car_id) {
@@ -272,30 +503,430 @@ impl CarSharing {
         Ok(format!("Car '{}' rented successfully for {} hours by '{}'", car_id, duration, user_id))
     }
 
+    // return_car closes the current rental of a car, early and late returns included,
+    // and settles its escrow: early returns are billed per the car's early return
+    // policy, overtime beyond the grace period is billed at the car's late fee
+    // rate, the distance reported by telematics at the car's mileage rate, the
+    // owner is paid, any excess is refunded to the renter and any shortfall is
+    // taken from the security deposit, the rest left as a top-up request on the
+    // booking and a debt of the renter
     #[payable]
     #[handle_result]
     pub fn return_car(&mut self, car_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
         let now: u64 = block_timestamp();
-        {
-            let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        let car: Car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?.clone();
+        // Find the booking that corresponds to the current rental, even if its end_time has passed;
+        // reservations can be driven without a check-in, so they are settled late like rentals
+        let current_booking_id: Option<String> = self
+            .bookings
+            .values()
+            .filter(|b| b.car_id == car_id && now >= b.start_time && b.status.is_open())
+            .max_by_key(|b| b.start_time)
+            .map(|b| b.booking_id.clone());
+        // only the renter or someone handling the car's check-in and check-out can return it
+        match current_booking_id.as_ref().and_then(|id| self.bookings.get(id)) {
+            Some(booking) => {
+                self.booking_party(booking, &caller, Permission::CheckInOut)?;
+            }
+            None => self.authorize(&caller, Scope::Car(&car), Permission::CheckInOut)?,
+        }
+        if let Some(car) = self.cars.get_mut(&car_id) {
             car.available = true;
         }
-        // Cancel the booking that corresponds to the current rental
-        if let Some(booking) = self
-            .bookings
-            .iter()
-            .find(|(_, b)| b.car_id == car_id && now >= b.start_time && now <= b.end_time)
-        {
-            let booking_id: String = booking.0.clone();
-            let user_id: String = booking.1.user_id.clone();
-            let deposit: u128 = booking.1.deposit;
-            self.bookings.remove(&booking_id);
-            log!("Event: BookingCancelled, booking_id: {}, user: {}, deposit_retained: {}", booking_id.clone(), user_id.clone(), deposit);
+        if let Some(booking_id) = current_booking_id {
+            let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+            let distance: u64 = self.trip_distance(&car, booking)?;
+            let booking: &mut Booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+            // settle with the terms agreed at booking time, not the car's current rates
+            let terms: PriceSnapshot = booking.pricing;
+            let overtime_fee: u128 = pricing::overtime_fee(booking.end_time, now, terms.grace_period, terms.late_fee_rate);
+            let rental_fee: u128 = pricing::early_return_fee(booking.rental_fee, booking.start_time, booking.end_time, now, terms.early_return_policy);
+            let mileage_fee: u128 = distance as u128 * terms.mileage_rate;
+            let total_due: u128 = rental_fee + overtime_fee + mileage_fee;
+            let escrow_payout: u128 = booking.deposit.min(total_due);
+            let refund: u128 = booking.deposit - escrow_payout;
+            // a shortfall is taken from the security deposit, what it cannot
+            // cover is left due and recorded as a debt of the renter
+            let security_payout: u128 = (total_due - escrow_payout).min(booking.security_deposit);
+            let owner_payout: u128 = escrow_payout + security_payout;
+            booking.security_deposit -= security_payout;
+            booking.amount_due = total_due - owner_payout;
+            booking.deposit = 0;
+            booking.fees += overtime_fee + mileage_fee;
+            booking.distance = distance;
+            booking.returned_at = Some(now);
+            booking.security_deposit_release_at = now + car.security_deposit_hold_hours * pricing::NANOS_PER_HOUR;
+            booking.status = BookingStatus::Completed;
+            let user_id: String = booking.user_id.clone();
+            let booking_fee: u128 = booking.rental_fee;
+            let amount_due: u128 = booking.amount_due;
+            if let Some(user) = self.users.get_mut(&user_id) {
+                user.completed_rentals += 1;
+                user.debt += amount_due;
+            }
+            // the car can no longer be opened with the renter's key
+            self.access_grants.remove(&booking_id);
+            self.pay_booking_owners(&booking_id, owner_payout);
+            self.transfer(&user_id, refund);
+            if rental_fee < booking_fee {
+                log!("Event: EarlyReturn, booking_id: {}, user: {}, returned_at: {}, rental_fee_refunded: {}", booking_id, user_id, now, booking_fee - rental_fee);
+            }
+            if overtime_fee > 0 {
+                log!("Event: LateReturn, booking_id: {}, user: {}, overtime_fee: {}", booking_id, user_id, overtime_fee);
+            }
+            if mileage_fee > 0 {
+                log!("Event: MileageBilled, booking_id: {}, user: {}, distance: {}, mileage_fee: {}", booking_id, user_id, distance, mileage_fee);
+            }
+            if amount_due > 0 {
+                log!("Event: TopUpRequested, booking_id: {}, user: {}, amount_due: {}", booking_id, user_id, amount_due);
+            }
+            log!("Event: BookingSettled, booking_id: {}, user: {}, owner_payout: {}, refund: {}", booking_id, user_id, owner_payout, refund);
         }
         log!("Event: CarReturned, car_id: {}", car_id.clone());
         Ok(format!("Car '{}' returned successfully", car_id))
     }
 
+    // top_up_booking pays what is left due on a settled booking, any excess is refunded
+    #[payable]
+    #[handle_result]
+    pub fn top_up_booking(&mut self, booking_id: String) -> Result<String, Error> {
+        let attached: u128 = attached_deposit().as_yoctonear();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.amount_due == 0 {
+            return Err(Error::NothingDue);
+        }
+        let paid: u128 = attached.min(booking.amount_due);
+        let booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        booking.amount_due -= paid;
+        let amount_due: u128 = booking.amount_due;
+        let user_id: String = booking.user_id.clone();
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.debt = user.debt.saturating_sub(paid);
+        }
+        self.pay_booking_owners(&booking_id, paid);
+        self.transfer(&predecessor_account_id().to_string(), attached - paid);
+        log!("Event: TopUpReceived, booking_id: {}, paid: {}, amount_due: {}", booking_id, paid, amount_due);
+        Ok(format!("Booking {} topped up, {} left due", booking_id, amount_due))
+    }
+
+    // mark_no_show lets the owner close a reservation the renter never picked up
+    // once the no-show grace period has passed: the no-show fee is taken from the
+    // escrow, the rest is released to the renter and the car is free again
+    #[handle_result]
+    pub fn mark_no_show(&mut self, booking_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        let car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::CheckInOut)?;
+        if booking.status != BookingStatus::Reserved || booking.picked_up() || self.was_driven(booking) {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if block_timestamp() <= booking.start_time.saturating_add(booking.pricing.no_show_grace) {
+            return Err(Error::NoShowTooEarly);
+        }
+        self.settle_no_show(&booking_id)?;
+        Ok(format!("Booking {} marked as no-show", booking_id))
+    }
+
+    // process_expired is a permissionless cleanup: it examines at most `limit`
+    // booking ids, continuing from where the previous call stopped, settles
+    // reservations that ended without ever being picked up or driven as
+    // no-shows and moves settled bookings past their end_time to the archive;
+    // the caller earns the cleanup fee paid with every booking archived
+    #[handle_result]
+    pub fn process_expired(&mut self, limit: u32) -> Result<String, Error> {
+        if limit == 0 || limit > MAX_CLEANUP_BATCH {
+            return Err(Error::InvalidLimit);
+        }
+        let now: u64 = block_timestamp();
+        // ids come from a counter, so walking them from the cursor costs at most
+        // `limit` lookups; ids of removed bookings are simply skipped
+        let mut id: u64 = self.expired_cursor;
+        let mut examined: u32 = 0;
+        let mut archived: u32 = 0;
+        let mut reward: u128 = 0;
+        while examined < limit && id < self.next_booking_id {
+            id += 1;
+            examined += 1;
+            let booking_id: String = id.to_string();
+            // a reservation driven without check-in is left for return_car to settle
+            let (status, end_time, picked_up) = match self.bookings.get(&booking_id) {
+                Some(b) => (b.status, b.end_time, b.picked_up() || self.was_driven(b)),
+                None => continue,
+            };
+            if end_time >= now {
+                continue;
+            }
+            if status == BookingStatus::Reserved && !picked_up {
+                self.settle_no_show(&booking_id)?;
+            }
+            if self.is_archivable(&booking_id) {
+                if let Some(mut booking) = self.bookings.remove(&booking_id) {
+                    reward += booking.cleanup_fee;
+                    booking.cleanup_fee = 0;
+                    self.archived_bookings.insert(booking_id.clone(), booking);
+                    archived += 1;
+                    log!("Event: BookingArchived, booking_id: {}", booking_id);
+                }
+            }
+        }
+        // resume after the last id examined, wrapping around once all were seen
+        self.expired_cursor = if id >= self.next_booking_id { 0 } else { id };
+        self.transfer(&predecessor_account_id().to_string(), reward);
+        log!("Event: ExpiredProcessed, examined: {}, archived: {}, reward: {}", examined, archived, reward);
+        Ok(format!("{} expired bookings archived", archived))
+    }
+
+    // set_cleanup_fee sets the fee charged on new bookings to reward their cleanup
+    #[handle_result]
+    pub fn set_cleanup_fee(&mut self, cleanup_fee: u128) -> Result<String, Error> {
+        self.assert_admin()?;
+        self.cleanup_fee = cleanup_fee;
+        log!("Event: CleanupFeeUpdated, cleanup_fee: {}", cleanup_fee);
+        Ok(format!("Cleanup fee set to {}", cleanup_fee))
+    }
+
+    // update_hourly_rate changes the price of future bookings, existing bookings
+    // keep the terms they were made with
+    #[handle_result]
+    pub fn update_hourly_rate(&mut self, car_id: String, hourly_rate: u128) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        self.set_hourly_rate(&car_id, hourly_rate)?;
+        Ok(format!("Hourly rate of car '{}' updated to {}", car_id, hourly_rate))
+    }
+
+    // set_no_show_policy lets owners configure the grace period and fee for no-shows
+    #[handle_result]
+    pub fn set_no_show_policy(&mut self, car_id: String, no_show_grace: u64, no_show_fee: u128) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.no_show_grace = no_show_grace;
+        car.no_show_fee = no_show_fee;
+        log!("Event: NoShowPolicyUpdated, car_id: {}, no_show_grace: {}, no_show_fee: {}", car_id, no_show_grace, no_show_fee);
+        Ok(format!("No-show policy of car '{}' updated", car_id))
+    }
+
+    // set_early_return_policy lets owners choose how early returns are refunded
+    #[handle_result]
+    pub fn set_early_return_policy(&mut self, car_id: String, policy: EarlyReturnPolicy) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.early_return_policy = policy;
+        log!("Event: EarlyReturnPolicyUpdated, car_id: {}, policy: {:?}", car_id, policy);
+        Ok(format!("Early return policy of car '{}' updated", car_id))
+    }
+
+    // release_security_deposit gives the security deposit back to the renter once
+    // the car's hold period after return is over; anyone can trigger it
+    #[handle_result]
+    pub fn release_security_deposit(&mut self, booking_id: String) -> Result<String, Error> {
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.status != BookingStatus::Completed || booking.security_deposit == 0 {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if block_timestamp() < booking.security_deposit_release_at {
+            return Err(Error::SecurityDepositHeld);
+        }
+        if self.has_open_claim(&booking_id) {
+            return Err(Error::DamageClaimPending);
+        }
+        let booking: &mut Booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        let released: u128 = booking.security_deposit;
+        booking.security_deposit = 0;
+        let user_id: String = booking.user_id.clone();
+        self.transfer(&user_id, released);
+        log!("Event: SecurityDepositReleased, booking_id: {}, user: {}, amount: {}", booking_id, user_id, released);
+        Ok(format!("Security deposit of booking {} released", booking_id))
+    }
+
+    // set_security_deposit lets owners set the security deposit of a car and how
+    // many hours after return it is held
+    #[handle_result]
+    pub fn set_security_deposit(&mut self, car_id: String, security_deposit: u128, hold_hours: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.security_deposit = security_deposit;
+        car.security_deposit_hold_hours = hold_hours;
+        log!("Event: SecurityDepositUpdated, car_id: {}, security_deposit: {}, hold_hours: {}", car_id, security_deposit, hold_hours);
+        Ok(format!("Security deposit of car '{}' updated", car_id))
+    }
+
+    // set_late_return_policy lets owners configure the grace period and late fee rate of a car
+    #[handle_result]
+    pub fn set_late_return_policy(&mut self, car_id: String, grace_period: u64, late_fee_rate: u128) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.grace_period = grace_period;
+        car.late_fee_rate = late_fee_rate;
+        log!("Event: LateReturnPolicyUpdated, car_id: {}, grace_period: {}, late_fee_rate: {}", car_id, grace_period, late_fee_rate);
+        Ok(format!("Late return policy of car '{}' updated", car_id))
+    }
+
+    // extend_booking pushes back the end of a reservation or an ongoing rental
+    // until its grace period is over, the extra hours are charged at the car's
+    // hourly rate
+    #[payable]
+    #[handle_result]
+    pub fn extend_booking(&mut self, booking_id: String, new_end_time: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if !booking.status.is_open() {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if new_end_time <= booking.end_time {
+            return Err(Error::InvalidBookingTime);
+        }
+        // overdue bookings are settled at return with the late fee instead
+        if block_timestamp() > booking.end_time.saturating_add(booking.pricing.grace_period) {
+            return Err(Error::BookingOverdue);
+        }
+        // the extension window must not collide with any other booking of the car
+        if self.has_overlapping_booking(&booking.car_id, booking.end_time, new_end_time, Some(booking_id.as_str())) {
+            return Err(Error::CarNotAvailable);
+        }
+        let extension_fee: u128 = pricing::extension_fee(booking.pricing.hourly_rate, booking.end_time, new_end_time);
+        let attached_payment: u128 = attached_deposit().as_yoctonear();
+        if attached_payment < extension_fee {
+            return Err(Error::InsufficientPayment);
+        }
+        let booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        let old_end_time: u64 = booking.end_time;
+        Self::record_change(booking);
+        booking.end_time = new_end_time;
+        booking.rental_fee += extension_fee;
+        booking.deposit += attached_payment;
+        // the renter's key keeps opening the car until the new end time
+        if let Some(grant) = self.access_grants.get_mut(&booking_id) {
+            grant.valid_until = new_end_time;
+        }
+        log!("Event: BookingExtended, booking_id: {}, user: {}, old_end_time: {}, new_end_time: {}, extension_fee: {}", booking_id, caller, old_end_time, new_end_time, extension_fee);
+        Ok(format!("Booking {} extended until {}", booking_id, new_end_time))
+    }
+
+    // modify_booking moves a reservation that has not started yet to new times
+    // and/or to another car of the same owner without any cancellation penalty;
+    // a higher price or security deposit must be paid with the call, escrow above
+    // a lower one is refunded
+    #[payable]
+    #[handle_result]
+    pub fn modify_booking(&mut self, booking_id: String, new_car_id: Option<String>, new_start_time: u64, new_end_time: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        if booking.user_id != caller {
+            return Err(Error::Unauthorized);
+        }
+        if booking.status != BookingStatus::Reserved || booking.picked_up() {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if new_start_time >= new_end_time || new_start_time < block_timestamp() {
+            return Err(Error::InvalidBookingTime);
+        }
+        let car_id: String = new_car_id.unwrap_or_else(|| booking.car_id.clone());
+        let current_car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        let new_car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        if new_car.owner_id != current_car.owner_id {
+            return Err(Error::DifferentOwner);
+        }
+        if !new_car.available || self.has_overlapping_booking(&car_id, new_start_time, new_end_time, Some(booking_id.as_str())) {
+            return Err(Error::CarNotAvailable);
+        }
+        // the renter must meet the requirements of the car they move to
+        if car_id != booking.car_id {
+            self.check_requirements(new_car, &caller)?;
+        }
+        // new terms are priced at the current rates of the car, and its security
+        // deposit replaces the one held so far
+        let new_pricing: PriceSnapshot = new_car.price_snapshot();
+        let new_rental_fee: u128 = pricing::rental_fee(new_pricing.hourly_rate, new_start_time, new_end_time);
+        let old_rental_fee: u128 = booking.rental_fee;
+        let new_security_deposit: u128 = new_car.security_deposit;
+        let new_payees: Vec<CoOwner> = new_car.shares();
+        let security_top_up: u128 = new_security_deposit.saturating_sub(booking.security_deposit);
+        let attached_payment: u128 = attached_deposit().as_yoctonear();
+        if attached_payment < new_rental_fee.saturating_sub(old_rental_fee) + security_top_up {
+            return Err(Error::InsufficientPayment);
+        }
+        let old_key: (String, String, u64) = (booking.car_id.clone(), booking.user_id.clone(), booking.start_time);
+        self.booking_keys.remove(&old_key);
+        self.booking_keys.insert((car_id.clone(), caller.clone(), new_start_time), booking_id.clone());
+        let booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        Self::record_change(booking);
+        booking.car_id = car_id.clone();
+        booking.start_time = new_start_time;
+        booking.end_time = new_end_time;
+        booking.rental_fee = new_rental_fee;
+        booking.pricing = new_pricing;
+        booking.payees = new_payees;
+        booking.deposit += attached_payment - security_top_up;
+        // only escrow exceeding the new price and security deposit above the
+        // new car's is given back
+        let security_refund: u128 = booking.security_deposit.saturating_sub(new_security_deposit);
+        booking.security_deposit = new_security_deposit;
+        let refund: u128 = booking.deposit.saturating_sub(new_rental_fee);
+        booking.deposit -= refund;
+        self.transfer(&caller, refund + security_refund);
+        log!("Event: BookingModified, booking_id: {}, user: {}, car_id: {}, start_time: {}, end_time: {}, old_rental_fee: {}, new_rental_fee: {}, refund: {}", booking_id, caller, car_id, new_start_time, new_end_time, old_rental_fee, new_rental_fee, refund);
+        Ok(format!("Booking {} moved to car '{}' from {} to {}", booking_id, car_id, new_start_time, new_end_time))
+    }
+
+    // check_in records odometer, fuel level and photos at pickup; the renter and
+    // the owner both call it with the same values to co-sign the report, from the
+    // start of the booking on; the rental becomes active once the renter signed
+    #[handle_result]
+    pub fn check_in(&mut self, booking_id: String, odometer: u64, fuel_level: u8, photos_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        let is_renter: bool = self.booking_party(booking, &caller, Permission::CheckInOut)?;
+        if !booking.status.is_open() {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if block_timestamp() < booking.start_time {
+            return Err(Error::CheckInTooEarly);
+        }
+        let report: ConditionReport = Self::sign_condition_report(booking.check_in.clone(), is_renter, odometer, fuel_level, photos_hash)?;
+        let start_odometer: Option<u64> = booking.start_odometer.or(self.telemetry.get(&booking.car_id).map(|reading| reading.odometer));
+        let booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        booking.check_in = Some(report);
+        booking.start_odometer = start_odometer;
+        if booking.picked_up() {
+            booking.status = BookingStatus::Active;
+        }
+        log!("Event: CheckIn, booking_id: {}, by: {}, odometer: {}, fuel_level: {}", booking_id, caller, odometer, fuel_level);
+        Ok(format!("Check-in for booking {} recorded by '{}'", booking_id, caller))
+    }
+
+    // check_out records the same data at return, it requires a prior check-in
+    #[handle_result]
+    pub fn check_out(&mut self, booking_id: String, odometer: u64, fuel_level: u8, photos_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let booking = self.bookings.get(&booking_id).ok_or(Error::BookingNotFound)?;
+        let is_renter: bool = self.booking_party(booking, &caller, Permission::CheckInOut)?;
+        let check_in = booking.check_in.as_ref().ok_or(Error::InvalidBookingStatus)?;
+        // the odometer can never go backwards during a trip
+        if odometer < check_in.odometer {
+            return Err(Error::InvalidConditionReport);
+        }
+        let report: ConditionReport = Self::sign_condition_report(booking.check_out.clone(), is_renter, odometer, fuel_level, photos_hash)?;
+        let booking = self.bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?;
+        booking.check_out = Some(report);
+        log!("Event: CheckOut, booking_id: {}, by: {}, odometer: {}, fuel_level: {}", booking_id, caller, odometer, fuel_level);
+        Ok(format!("Check-out for booking {} recorded by '{}'", booking_id, caller))
+    }
+
     // read-only functions
     pub fn is_owner(&self, account_id: &AccountId) -> bool {
         self.owners_accounts.contains(account_id)
@@ -307,12 +938,45 @@ impl CarSharing {
 
     #[handle_result]
     pub fn get_booking_id(&self, car_id: String, user_id: String, start_time: u64) -> Result<String, String> {
-        for booking in self.bookings.values() {
-            if booking.car_id == car_id && booking.user_id == user_id && booking.start_time == start_time {
-                return Ok(booking.booking_id.clone());
-            }
+        self.booking_keys
+            .get(&(car_id, user_id, start_time))
+            .cloned()
+            .ok_or("No booking found for the specified car, user, and start time".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_booking(&self, booking_id: String) -> Result<Booking, String> {
+        self.bookings
+            .get(&booking_id)
+            .or_else(|| self.archived_bookings.get(&booking_id))
+            .cloned()
+            .ok_or("Booking not found".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_trip_summary(&self, booking_id: String) -> Result<TripSummary, String> {
+        let booking = self.bookings.get(&booking_id).ok_or("Booking not found".to_string())?;
+        match (&booking.check_in, &booking.check_out) {
+            (Some(check_in), Some(check_out)) => Ok(TripSummary {
+                booking_id: booking_id.clone(),
+                distance: check_out.odometer - check_in.odometer,
+                fuel_used: check_in.fuel_level as i16 - check_out.fuel_level as i16,
+                fully_signed: check_in.renter_signed && check_in.owner_signed && check_out.renter_signed && check_out.owner_signed,
+            }),
+            _ => Err("Trip has no check-in and check-out reports".to_string()),
         }
-        Err("No booking found for the specified car, user, and start time".to_string())
+    }
+
+    // get_car_calendar lists the periods a car is reserved or was rented, using the
+    // effective end time of bookings that were returned early or late
+    pub fn get_car_calendar(&self, car_id: String) -> Vec<(String, u64, u64)> {
+        let mut calendar: Vec<(String, u64, u64)> = self.bookings
+            .values()
+            .filter(|b| b.car_id == car_id)
+            .map(|b| (b.booking_id.clone(), b.start_time, b.returned_at.unwrap_or(b.end_time)))
+            .collect();
+        calendar.sort_by_key(|(_, start_time, _)| *start_time);
+        calendar
     }
 
     #[handle_result]
@@ -366,6 +1030,220 @@ impl CarSharing {
     }
 }
 
+// internal helpers, not exposed as contract methods
+impl CarSharing {
+    // ids of all bookings of a car that are not fully settled yet
+    fn car_booking_ids(&self, car_id: &str) -> Vec<String> {
+        self.bookings
+            .values()
+            .filter(|b| b.car_id == car_id && (b.status.is_blocking() || b.amount_due > 0))
+            .map(|b| b.booking_id.clone())
+            .collect()
+    }
+
+    // true when a booking still blocking the car or a maintenance window overlaps
+    // the given period, `exclude_booking_id` lets a booking be checked against all the others
+    fn has_overlapping_booking(&self, car_id: &str, start_time: u64, end_time: u64, exclude_booking_id: Option<&str>) -> bool {
+        if self.in_maintenance(car_id, start_time, end_time) {
+            return true;
+        }
+        self.bookings.values().any(|booking| {
+            booking.car_id == car_id
+                && booking.status.is_blocking()
+                && Some(booking.booking_id.as_str()) != exclude_booking_id
+                && ((start_time >= booking.start_time && start_time < booking.end_time)
+                || (end_time > booking.start_time && end_time <= booking.end_time)
+                || (start_time <= booking.start_time && end_time >= booking.end_time))
+        })
+    }
+
+    // returns true when the caller is the renter of the booking, false when it
+    // acts for the car owner with the given permission; claims, disputes and
+    // reviews belong to the owner the booking was made with, who is paid its
+    // payouts even after the car was transferred or removed
+    fn booking_party(&self, booking: &Booking, caller: &str, permission: Permission) -> Result<bool, Error> {
+        if booking.user_id == caller {
+            return Ok(true);
+        }
+        if permission == Permission::Owner {
+            self.authorize(caller, Scope::Owner(&booking.owner_id), permission)?;
+            return Ok(false);
+        }
+        let car = self.cars.get(&booking.car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(caller, Scope::Car(car), permission)?;
+        Ok(false)
+    }
+
+    // the first party creates the report, the other party co-signs it by submitting identical values
+    fn sign_condition_report(existing: Option<ConditionReport>, is_renter: bool, odometer: u64, fuel_level: u8, photos_hash: String) -> Result<ConditionReport, Error> {
+        if fuel_level > 100 {
+            return Err(Error::InvalidConditionReport);
+        }
+        let mut report = match existing {
+            Some(report) => {
+                if (is_renter && report.renter_signed) || (!is_renter && report.owner_signed) {
+                    return Err(Error::ReportAlreadySigned);
+                }
+                if report.odometer != odometer || report.fuel_level != fuel_level || report.photos_hash != photos_hash {
+                    return Err(Error::ConditionReportMismatch);
+                }
+                report
+            }
+            None => ConditionReport {
+                odometer,
+                fuel_level,
+                photos_hash,
+                reported_at: block_timestamp(),
+                renter_signed: false,
+                owner_signed: false,
+            },
+        };
+        if is_renter {
+            report.renter_signed = true;
+        } else {
+            report.owner_signed = true;
+        }
+        Ok(report)
+    }
+
+    // settle a reservation that was never picked up: the car's no-show fee goes to
+    // the owner out of the escrow and the rest is released to the renter
+    fn settle_no_show(&mut self, booking_id: &str) -> Result<(), Error> {
+        let booking = self.bookings.get(booking_id).ok_or(Error::BookingNotFound)?;
+        let no_show_fee: u128 = booking.pricing.no_show_fee.min(booking.deposit);
+        let booking = self.bookings.get_mut(booking_id).ok_or(Error::BookingNotFound)?;
+        let released: u128 = booking.deposit - no_show_fee + booking.security_deposit;
+        booking.deposit = 0;
+        booking.security_deposit = 0;
+        booking.fees += no_show_fee;
+        booking.status = BookingStatus::NoShow;
+        let user_id: String = booking.user_id.clone();
+        self.pay_booking_owners(booking_id, no_show_fee);
+        self.transfer(&user_id, released);
+        log!("Event: BookingNoShow, booking_id: {}, user: {}, no_show_fee: {}, released: {}", booking_id, user_id, no_show_fee, released);
+        Ok(())
+    }
+
+    // a booking can leave the active map once it no longer blocks the car, holds
+    // no funds and can no longer be claimed against
+    fn is_archivable(&self, booking_id: &str) -> bool {
+        match self.bookings.get(booking_id) {
+            Some(b) => {
+                !b.status.is_blocking()
+                    && b.deposit == 0
+                    && b.security_deposit == 0
+                    && b.amount_due == 0
+                    && b.security_deposit_release_at < block_timestamp()
+                    && !b.claim_window_open(block_timestamp())
+                    && !self.has_open_claim(booking_id)
+            }
+            None => false,
+        }
+    }
+
+    // booking ids come from a monotonic counter so a booking can never overwrite
+    // another one; the (car, user, start time) key is kept for get_booking_id
+    fn new_booking_id(&mut self, car_id: &str, user_id: &str, start_time: u64) -> String {
+        self.next_booking_id += 1;
+        let booking_id: String = self.next_booking_id.to_string();
+        self.booking_keys.insert((car_id.to_string(), user_id.to_string(), start_time), booking_id.clone());
+        booking_id
+    }
+
+    // make sure the caller can list a new car for this owner
+    fn check_new_car(&self, caller: &str, car_id: &str, owner_id: &str, hourly_rate: u128) -> Result<(), Error> {
+        // Ensure caller has permission to add a car for this owner
+        self.authorize(caller, Scope::Owner(owner_id), Permission::AddCars)?;
+        if self.cars.contains_key(car_id) {
+            return Err(Error::CarAlreadyExists);
+        }
+        if !self.owners.contains_key(owner_id) {
+            return Err(Error::OwnerNotFound);
+        }
+        // Validate the hourly rate to prevent invalid inputs
+        if hourly_rate == 0 {
+            return Err(Error::InvalidHourlyRate);
+        }
+        Ok(())
+    }
+
+    // list a car checked by check_new_car and mint its token
+    fn insert_car(&mut self, car_id: &str, owner_id: &str, hourly_rate: u128) {
+        self.cars.insert(
+            car_id.to_string(),
+            Car {
+                car_id: car_id.to_string(),
+                owner_id: owner_id.to_string(),
+                available: true,
+                hourly_rate,
+                listed_at: block_timestamp(),
+                ..Default::default()
+            },
+        );
+        nft::log_nft_event("nft_mint", near_sdk::serde_json::json!([{ "owner_id": owner_id, "token_ids": [car_id] }]));
+        log!("Event: CarAdded, car_id: {}, owner: {}", car_id, owner_id);
+    }
+
+    // change the rate of a car for future bookings and record the old and new rates
+    fn set_hourly_rate(&mut self, car_id: &str, hourly_rate: u128) -> Result<(), Error> {
+        if hourly_rate == 0 {
+            return Err(Error::InvalidHourlyRate);
+        }
+        let car: &mut Car = self.cars.get_mut(car_id).ok_or(Error::CarNotFound)?;
+        let old_rate: u128 = car.hourly_rate;
+        car.hourly_rate = hourly_rate;
+        log!("Event: RateUpdated, car_id: {}, old_rate: {}, new_rate: {}", car_id, old_rate, hourly_rate);
+        Ok(())
+    }
+
+    // delete a car without bookings left to settle and burn its token
+    fn remove_car(&mut self, car_id: &str) -> Result<(), Error> {
+        // refuse deletion while renters still hold bookings on this car
+        if !self.car_booking_ids(car_id).is_empty() {
+            return Err(Error::CarHasBookings);
+        }
+        let car: Car = self.cars.remove(car_id).ok_or(Error::CarNotFound)?;
+        self.maintenance_windows.remove(car_id);
+        self.telematics_devices.remove(car_id);
+        self.telemetry.remove(car_id);
+        nft::log_nft_event("nft_burn", near_sdk::serde_json::json!([{ "owner_id": car.owner_id, "token_ids": [car_id] }]));
+        log!("Event: Car deleted, car_id: {}", car_id);
+        Ok(())
+    }
+
+    // remove a cancelled booking along with its lookup key and access grant
+    fn remove_booking(&mut self, booking_id: &str) -> Option<Booking> {
+        let booking = self.bookings.remove(booking_id)?;
+        let key: (String, String, u64) = (booking.car_id.clone(), booking.user_id.clone(), booking.start_time);
+        if self.booking_keys.get(&key).map(|id| id.as_str()) == Some(booking_id) {
+            self.booking_keys.remove(&key);
+        }
+        self.access_grants.remove(booking_id);
+        Some(booking)
+    }
+
+    // keep the current terms of a booking in its history before they change
+    fn record_change(booking: &mut Booking) {
+        booking.history.push(BookingChange {
+            changed_at: block_timestamp(),
+            car_id: booking.car_id.clone(),
+            start_time: booking.start_time,
+            end_time: booking.end_time,
+            rental_fee: booking.rental_fee,
+        });
+    }
+
+    // send funds held by the contract to an account, skipping empty amounts
+    fn transfer(&self, account_id: &str, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        if let Ok(account_id) = account_id.parse::<AccountId>() {
+            Promise::new(account_id).transfer(NearToken::from_yoctonear(amount));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     InvalidProof,
@@ -384,27 +1262,160 @@ pub enum Error {
     InvalidBookingTime,
     BookingNotFound,
     InvalidAccountId,
+    CarHasBookings,
+    InvalidBookingStatus,
+    InvalidConditionReport,
+    ConditionReportMismatch,
+    ReportAlreadySigned,
+    NothingDue,
+    DifferentOwner,
+    NoShowTooEarly,
+    CheckInTooEarly,
+    InvalidLimit,
+    DisputeAlreadyOpen,
+    DisputeNotFound,
+    DisputeClosed,
+    DisputeDeadlineNotReached,
+    NothingToDispute,
+    NotArbitrator,
+    InvalidShare,
+    InvalidRating,
+    AlreadyReviewed,
+    RatingTooLow,
+    NotEnoughRentals,
+    LicenseTooRecent,
+    RenterNotApproved,
+    InvalidLicenseDate,
+    SecurityDepositHeld,
+    ClaimWindowClosed,
+    InvalidClaimAmount,
+    InvalidClaimStatus,
+    DamageClaimExists,
+    DamageClaimNotFound,
+    DamageClaimPending,
+    ClaimResponseOpen,
+    BookingOverdue,
+    RentalInProgress,
+    ManagerRequired,
+    OutstandingDebt,
+    InsuranceTierNotFound,
+    RequiresOneYocto,
+    InvalidReceiver,
+    BookingTokenExists,
+    BookingNotTransferable,
+    TransfersDisabled,
+    NotVerifier,
+    CredentialNotFound,
+    InvalidCredential,
+    MissingCredential,
+    ProposalNotFound,
+    ProposalExecuted,
+    CarCoOwned,
+    InvalidPermission,
+    DelegationNotFound,
+    InsufficientGas,
+    MaintenanceNotFound,
+    InvalidOdometer,
+    InvalidPublicKey,
+    LockNotRegistered,
+    InvalidSignature,
+    AccessGrantNotFound,
+    ReporterInUse,
+    DeviceNotFound,
+    InvalidTelemetry,
+    InvalidGeohash,
+    WrongReturnLocation,
+}
+
+impl Error {
+    // description of the error, also reported per item by batch methods
+    pub fn message(&self) -> &'static str {
+        match self {
+            Error::InvalidProof => "Invalid proof provided",
+            Error::UserAlreadyExists => "User already exists",
+            Error::OwnerAlreadyExists => "Owner already exists",
+            Error::CarAlreadyExists => "Car already exists",
+            Error::UserNotFound => "User not found",
+            Error::OwnerNotFound => "Owner not found",
+            Error::CarNotFound => "Car not found",
+            Error::CarNotAvailable => "Car not available",
+            Error::InsufficientDeposit => "Insufficient deposit",
+            Error::InsufficientPayment => "Insufficient payment",
+            Error::Unauthorized => "Unauthorized",
+            Error::InvalidUser => "Invalid user",
+            Error::InvalidHourlyRate => "Invalid hourly rate",
+            Error::InvalidBookingTime => "Invalid booking time",
+            Error::BookingNotFound => "Booking not found",
+            Error::InvalidAccountId => "Invalid account ID",
+            Error::CarHasBookings => "Car has active or future bookings",
+            Error::InvalidBookingStatus => "Invalid booking status for this action",
+            Error::InvalidConditionReport => "Invalid condition report",
+            Error::ConditionReportMismatch => "Condition report does not match the one to co-sign",
+            Error::ReportAlreadySigned => "Report already signed by this party",
+            Error::NothingDue => "Nothing is due on this booking",
+            Error::DifferentOwner => "Cars belong to different owners",
+            Error::NoShowTooEarly => "No-show grace period has not passed yet",
+            Error::CheckInTooEarly => "Booking has not started yet",
+            Error::InvalidLimit => "Invalid batch limit",
+            Error::DisputeAlreadyOpen => "A dispute is already open on this booking",
+            Error::DisputeNotFound => "Dispute not found",
+            Error::DisputeClosed => "Dispute is closed",
+            Error::DisputeDeadlineNotReached => "Dispute deadline has not passed yet",
+            Error::NothingToDispute => "No escrowed funds to dispute",
+            Error::NotArbitrator => "Account is not a registered arbitrator",
+            Error::InvalidShare => "Share must be at most 10000 basis points",
+            Error::InvalidRating => "Rating must be between 1 and 5",
+            Error::AlreadyReviewed => "Booking already reviewed by this party",
+            Error::RatingTooLow => "Renter rating is below the minimum required for this car",
+            Error::NotEnoughRentals => "Renter has not completed enough rentals for this car",
+            Error::LicenseTooRecent => "Renter's driving license is too recent for this car",
+            Error::RenterNotApproved => "This car requires approval from its owner",
+            Error::InvalidLicenseDate => "License issue date cannot be in the future",
+            Error::SecurityDepositHeld => "Security deposit is still held",
+            Error::ClaimWindowClosed => "Claim window is closed",
+            Error::InvalidClaimAmount => "Invalid claim amount",
+            Error::InvalidClaimStatus => "Damage claim is not pending",
+            Error::DamageClaimExists => "A damage claim was already filed for this booking",
+            Error::DamageClaimNotFound => "Damage claim not found",
+            Error::DamageClaimPending => "A damage claim is pending on this booking",
+            Error::ClaimResponseOpen => "Renter can still respond to the damage claim",
+            Error::BookingOverdue => "Booking is past its end time and grace period",
+            Error::RentalInProgress => "The rental is in progress, return the car first",
+            Error::ManagerRequired => "Co-owned cars need a manager",
+            Error::OutstandingDebt => "Renter has unpaid damage claims or booking balances",
+            Error::InsuranceTierNotFound => "Insurance tier not found",
+            Error::RequiresOneYocto => "Requires attached deposit of exactly 1 yoctoNEAR",
+            Error::InvalidReceiver => "Receiver must differ from the current owner",
+            Error::BookingTokenExists => "Booking token already minted",
+            Error::BookingNotTransferable => "Booking cannot be transferred",
+            Error::TransfersDisabled => "Owner disabled booking transfers for this car",
+            Error::NotVerifier => "Account is not a registered verifier",
+            Error::CredentialNotFound => "Credential not found",
+            Error::InvalidCredential => "Credential expiry must be in the future",
+            Error::MissingCredential => "Renter lacks a credential required for this car",
+            Error::ProposalNotFound => "Proposal not found",
+            Error::ProposalExecuted => "Proposal was already executed",
+            Error::CarCoOwned => "Co-owned cars can only be changed by their co-owners",
+            Error::InvalidPermission => "Permission cannot be granted",
+            Error::DelegationNotFound => "No permissions granted to this manager",
+            Error::InsufficientGas => "Not enough gas attached for this batch",
+            Error::MaintenanceNotFound => "Maintenance window not found",
+            Error::InvalidOdometer => "Odometer reading is lower than the last known one",
+            Error::InvalidPublicKey => "Only ed25519 public keys can open a car",
+            Error::LockNotRegistered => "No smart lock registered for this car",
+            Error::InvalidSignature => "Access grant is not signed by the lock's authority key",
+            Error::AccessGrantNotFound => "Access grant not found",
+            Error::ReporterInUse => "This account already reports for another device of the car",
+            Error::DeviceNotFound => "Telematics device not found",
+            Error::InvalidTelemetry => "Telemetry reading is out of order or out of range",
+            Error::InvalidGeohash => "Invalid geohash",
+            Error::WrongReturnLocation => "Car is not in its return area",
+        }
+    }
 }
 
 impl FunctionError for Error {
     fn panic(&self) -> ! {
-        match self {
-            Error::InvalidProof => near_sdk::env::panic_str("Invalid proof provided"),
-            Error::UserAlreadyExists => near_sdk::env::panic_str("User already exists"),
-            Error::OwnerAlreadyExists => near_sdk::env::panic_str("Owner already exists"),
-            Error::CarAlreadyExists => near_sdk::env::panic_str("Car already exists"),
-            Error::UserNotFound => near_sdk::env::panic_str("User not found"),
-            Error::OwnerNotFound => near_sdk::env::panic_str("Owner not found"),
-            Error::CarNotFound => near_sdk::env::panic_str("Car not found"),
-            Error::CarNotAvailable => near_sdk::env::panic_str("Car not available"),
-            Error::InsufficientDeposit => near_sdk::env::panic_str("Insufficient deposit"),
-            Error::InsufficientPayment => near_sdk::env::panic_str("Insufficient payment"),
-            Error::Unauthorized => near_sdk::env::panic_str("Unauthorized"),
-            Error::InvalidUser => near_sdk::env::panic_str("Invalid user"),
-            Error::InvalidHourlyRate => near_sdk::env::panic_str("Invalid hourly rate"),
-            Error::InvalidBookingTime => near_sdk::env::panic_str("Invalid booking time"),
-            Error::BookingNotFound => near_sdk::env::panic_str("Booking not found"),
-            Error::InvalidAccountId => near_sdk::env::panic_str("Invalid account ID"),
-        }
-    }
-}
\ No newline at end of file
+        near_sdk::env::panic_str(self.message())
+    }
+}
@@ -0,0 +1,169 @@
+// Maintenance of cars: owners schedule maintenance windows that block bookings,
+// keep a service log per car, and get a service-due flag once the distance or
+// time since the last service reaches the car's service interval.
+use crate::*;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct MaintenanceWindow {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub reason: String,
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct ServiceEntry {
+    pub serviced_at: u64,
+    pub odometer: u64, // in km
+    pub work_done: String,
+    pub document_hash: String, // invoice or inspection report
+}
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ServiceStatus {
+    pub car_id: String,
+    pub odometer: u64, // latest known reading
+    pub distance_since_service: u64,
+    pub time_since_service: u64,
+    pub last_service: Option<ServiceEntry>,
+    pub service_due: bool,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // schedule_maintenance blocks a period for maintenance, it cannot overlap
+    // bookings or other maintenance windows of the car
+    #[handle_result]
+    pub fn schedule_maintenance(&mut self, car_id: String, start_time: u64, end_time: u64, reason: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        if start_time >= end_time {
+            return Err(Error::InvalidBookingTime);
+        }
+        if self.has_overlapping_booking(&car_id, start_time, end_time, None) {
+            return Err(Error::CarNotAvailable);
+        }
+        self.maintenance_windows.entry(car_id.clone()).or_default().push(MaintenanceWindow {
+            start_time,
+            end_time,
+            reason: reason.clone(),
+        });
+        log!("Event: MaintenanceScheduled, car_id: {}, start_time: {}, end_time: {}, reason: {}", car_id, start_time, end_time, reason);
+        Ok(format!("Maintenance of car '{}' scheduled from {} to {}", car_id, start_time, end_time))
+    }
+
+    #[handle_result]
+    pub fn cancel_maintenance(&mut self, car_id: String, start_time: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        let windows: &mut Vec<MaintenanceWindow> = self.maintenance_windows.get_mut(&car_id).ok_or(Error::MaintenanceNotFound)?;
+        let index: usize = windows.iter().position(|w| w.start_time == start_time).ok_or(Error::MaintenanceNotFound)?;
+        windows.remove(index);
+        log!("Event: MaintenanceCancelled, car_id: {}, start_time: {}", car_id, start_time);
+        Ok(format!("Maintenance of car '{}' starting at {} cancelled", car_id, start_time))
+    }
+
+    // record_service adds an entry to the service log, which resets the service-due flag
+    #[handle_result]
+    pub fn record_service(&mut self, car_id: String, odometer: u64, work_done: String, document_hash: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        // the odometer never goes backwards
+        if odometer < self.latest_odometer(&car_id) {
+            return Err(Error::InvalidOdometer);
+        }
+        self.service_logs.entry(car_id.clone()).or_default().push(ServiceEntry {
+            serviced_at: block_timestamp(),
+            odometer,
+            work_done: work_done.clone(),
+            document_hash: document_hash.clone(),
+        });
+        log!("Event: ServiceRecorded, car_id: {}, odometer: {}, work_done: {}, document_hash: {}", car_id, odometer, work_done, document_hash);
+        Ok(format!("Service of car '{}' recorded", car_id))
+    }
+
+    // set_service_interval sets after how many km or how long (in nanoseconds) a car
+    // is due for service, 0 disables either limit
+    #[handle_result]
+    pub fn set_service_interval(&mut self, car_id: String, service_interval_km: u64, service_interval: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.service_interval_km = service_interval_km;
+        car.service_interval = service_interval;
+        log!("Event: ServiceIntervalUpdated, car_id: {}, service_interval_km: {}, service_interval: {}", car_id, service_interval_km, service_interval);
+        Ok(format!("Service interval of car '{}' updated", car_id))
+    }
+
+    // read-only functions
+    pub fn get_maintenance_windows(&self, car_id: String) -> Vec<MaintenanceWindow> {
+        self.maintenance_windows.get(&car_id).cloned().unwrap_or_default()
+    }
+
+    pub fn get_service_log(&self, car_id: String) -> Vec<ServiceEntry> {
+        self.service_logs.get(&car_id).cloned().unwrap_or_default()
+    }
+
+    #[handle_result]
+    pub fn get_service_status(&self, car_id: String) -> Result<ServiceStatus, String> {
+        let car = self.cars.get(&car_id).ok_or("Car not found".to_string())?;
+        Ok(self.service_status(car))
+    }
+
+    // cars of an owner that are due for service
+    pub fn list_cars_due_for_service(&self, owner_id: String) -> Vec<ServiceStatus> {
+        self.cars
+            .values()
+            .filter(|car| car.owner_id == owner_id)
+            .map(|car| self.service_status(car))
+            .filter(|status| status.service_due)
+            .collect()
+    }
+}
+
+impl CarSharing {
+    // true when a maintenance window of the car overlaps the given period
+    pub(crate) fn in_maintenance(&self, car_id: &str, start_time: u64, end_time: u64) -> bool {
+        self.maintenance_windows
+            .get(car_id)
+            .is_some_and(|windows| windows.iter().any(|w| start_time < w.end_time && end_time > w.start_time))
+    }
+
+    // highest odometer reading known for a car, from the service log, the
+    // condition reports of its bookings and telematics
+    pub(crate) fn latest_odometer(&self, car_id: &str) -> u64 {
+        let serviced: u64 = self.service_logs.get(car_id).and_then(|log| log.last()).map(|entry| entry.odometer).unwrap_or(0);
+        let reported: u64 = self
+            .bookings
+            .values()
+            .chain(self.archived_bookings.values())
+            .filter(|b| b.car_id == car_id)
+            .flat_map(|b| [b.check_in.as_ref(), b.check_out.as_ref()])
+            .flatten()
+            .map(|report| report.odometer)
+            .max()
+            .unwrap_or(0);
+        let telemetry: u64 = self.telemetry.get(car_id).map(|reading| reading.odometer).unwrap_or(0);
+        serviced.max(reported).max(telemetry)
+    }
+
+    fn service_status(&self, car: &Car) -> ServiceStatus {
+        let last_service: Option<ServiceEntry> = self.service_logs.get(&car.car_id).and_then(|log| log.last()).cloned();
+        let odometer: u64 = self.latest_odometer(&car.car_id);
+        // without a service yet, distance and time count from zero and from listing
+        let (serviced_odometer, serviced_at): (u64, u64) = last_service.as_ref().map(|s| (s.odometer, s.serviced_at)).unwrap_or((0, car.listed_at));
+        let distance_since_service: u64 = odometer.saturating_sub(serviced_odometer);
+        let time_since_service: u64 = block_timestamp().saturating_sub(serviced_at);
+        let service_due: bool = (car.service_interval_km > 0 && distance_since_service >= car.service_interval_km)
+            || (car.service_interval > 0 && time_since_service >= car.service_interval);
+        ServiceStatus {
+            car_id: car.car_id.clone(),
+            odometer,
+            distance_since_service,
+            time_since_service,
+            last_service,
+            service_due,
+        }
+    }
+}
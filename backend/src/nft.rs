@@ -0,0 +1,232 @@
+// Cars as NEP-171 non-fungible tokens with NEP-177 metadata: the token id is the
+// car id and the token owner is the car owner. Transferring a token moves the
+// listing and its future earnings, bookings made before the transfer still pay
+// out to the previous owner.
+use crate::*;
+use near_sdk::env::current_account_id;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, json};
+use near_sdk::{Gas, PromiseResult};
+
+pub const NFT_METADATA_SPEC: &str = "nft-1.0.0";
+pub const NFT_STANDARD_NAME: &str = "nep171";
+pub const NFT_STANDARD_VERSION: &str = "1.0.0";
+pub const GAS_FOR_NFT_ON_TRANSFER: Gas = Gas::from_tgas(25);
+pub const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(15);
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct NFTContractMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>,
+}
+#[derive(Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct TokenMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<String>,
+    pub copies: Option<u64>,
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub starts_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub extra: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>,
+}
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Token {
+    pub token_id: String,
+    pub owner_id: String,
+    pub metadata: Option<TokenMetadata>,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // nft_transfer hands a car over to another account, 1 yoctoNEAR must be
+    // attached so the call is confirmed with a full access key
+    #[payable]
+    #[handle_result]
+    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>) -> Result<String, Error> {
+        let sender_id = predecessor_account_id().to_string();
+        if attached_deposit().as_yoctonear() != 1 {
+            return Err(Error::RequiresOneYocto);
+        }
+        // approvals (NEP-178) are not supported, only the owner can transfer
+        if approval_id.is_some() {
+            return Err(Error::Unauthorized);
+        }
+        self.transfer_car(&sender_id, receiver_id.as_str(), &token_id, memo.as_deref())?;
+        Ok(format!("Car '{}' transferred to '{}'", token_id, receiver_id))
+    }
+
+    // nft_transfer_call transfers the car and notifies the receiver contract,
+    // which can ask for the car back by returning true from nft_on_transfer
+    #[payable]
+    pub fn nft_transfer_call(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>, msg: String) -> Promise {
+        let sender_id = predecessor_account_id().to_string();
+        if attached_deposit().as_yoctonear() != 1 {
+            Error::RequiresOneYocto.panic();
+        }
+        if approval_id.is_some() {
+            Error::Unauthorized.panic();
+        }
+        if let Err(error) = self.transfer_car(&sender_id, receiver_id.as_str(), &token_id, memo.as_deref()) {
+            error.panic();
+        }
+        let on_transfer_args = json!({
+            "sender_id": sender_id,
+            "previous_owner_id": sender_id,
+            "token_id": token_id,
+            "msg": msg,
+        });
+        let resolve_args = json!({
+            "previous_owner_id": sender_id,
+            "receiver_id": receiver_id,
+            "token_id": token_id,
+        });
+        Promise::new(receiver_id)
+            .function_call("nft_on_transfer".to_string(), on_transfer_args.to_string().into_bytes(), NearToken::from_yoctonear(0), GAS_FOR_NFT_ON_TRANSFER)
+            .then(Promise::new(current_account_id()).function_call(
+                "nft_resolve_transfer".to_string(),
+                resolve_args.to_string().into_bytes(),
+                NearToken::from_yoctonear(0),
+                GAS_FOR_RESOLVE_TRANSFER,
+            ))
+    }
+
+    // nft_resolve_transfer returns the car to its previous owner when the
+    // receiver asked for it or failed, returns true when the transfer stands
+    #[private]
+    pub fn nft_resolve_transfer(&mut self, previous_owner_id: AccountId, receiver_id: AccountId, token_id: String) -> bool {
+        let must_return: bool = match near_sdk::env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(true),
+            _ => true,
+        };
+        if !must_return {
+            return true;
+        }
+        // the receiver may have passed the car on already
+        if self.cars.get(&token_id).map(|car| car.owner_id.as_str()) != Some(receiver_id.as_str()) {
+            return true;
+        }
+        self.transfer_car(receiver_id.as_str(), previous_owner_id.as_str(), &token_id, None).is_err()
+    }
+
+    // read-only functions
+    pub fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Partage Cars".to_string(),
+            symbol: "PCAR".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    pub fn nft_token(&self, token_id: String) -> Option<Token> {
+        self.cars.get(&token_id).map(car_token)
+    }
+
+    pub fn nft_total_supply(&self) -> U128 {
+        U128(self.cars.len() as u128)
+    }
+
+    pub fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let cars: Vec<&Car> = self.cars.values().collect();
+        page(cars, from_index, limit)
+    }
+
+    pub fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        U128(self.cars.values().filter(|car| car.owner_id == account_id.as_str()).count() as u128)
+    }
+
+    pub fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let cars: Vec<&Car> = self.cars.values().filter(|car| car.owner_id == account_id.as_str()).collect();
+        page(cars, from_index, limit)
+    }
+}
+
+impl CarSharing {
+    // move a car to a new owner: existing bookings keep paying the owners they
+    // were made with and stay theirs to claim on, the receiver gets an owner
+    // account if needed and registers its own telematics devices and lock key
+    pub(crate) fn transfer_car(&mut self, sender_id: &str, receiver_id: &str, token_id: &str, memo: Option<&str>) -> Result<(), Error> {
+        let car = self.cars.get(token_id).ok_or(Error::CarNotFound)?;
+        self.authorize(sender_id, Scope::Car(car), Permission::Owner)?;
+        if car.co_owners.len() > 1 {
+            return Err(Error::CarCoOwned);
+        }
+        if receiver_id == sender_id {
+            return Err(Error::InvalidReceiver);
+        }
+        if !self.owners.contains_key(receiver_id) {
+            let account_id: AccountId = receiver_id.parse().map_err(|_| Error::InvalidAccountId)?;
+            self.owners.insert(
+                receiver_id.to_string(),
+                Owner {
+                    owner_id: receiver_id.to_string(),
+                    name: receiver_id.to_string(),
+                    ..Default::default()
+                },
+            );
+            self.owners_accounts.push(account_id);
+            log!("Event::OwnerCreated, owner_id: {}", receiver_id);
+        }
+        if let Some(car) = self.cars.get_mut(token_id) {
+            car.owner_id = receiver_id.to_string();
+            // the new owner holds the car alone and picks its own manager
+            car.co_owners.clear();
+            car.manager = None;
+            // the previous owner's lock key can no longer sign access grants
+            car.lock_device_id = None;
+            car.lock_authority_key = None;
+        }
+        // nor can the previous owner's reporters feed readings for the car
+        self.telematics_devices.remove(token_id);
+        log_nft_event(
+            "nft_transfer",
+            json!([{ "old_owner_id": sender_id, "new_owner_id": receiver_id, "token_ids": [token_id], "memo": memo }]),
+        );
+        log!("Event: CarTransferred, car_id: {}, from: {}, to: {}", token_id, sender_id, receiver_id);
+        Ok(())
+    }
+}
+
+// NEP-297 event log, read by indexers and wallets
+pub(crate) fn log_nft_event(event: &str, data: serde_json::Value) {
+    let event = json!({
+        "standard": NFT_STANDARD_NAME,
+        "version": NFT_STANDARD_VERSION,
+        "event": event,
+        "data": data,
+    });
+    log!("EVENT_JSON:{}", event);
+}
+
+fn car_token(car: &Car) -> Token {
+    Token {
+        token_id: car.car_id.clone(),
+        owner_id: car.owner_id.clone(),
+        metadata: Some(TokenMetadata {
+            title: Some(format!("Car {}", car.car_id)),
+            copies: Some(1),
+            ..Default::default()
+        }),
+    }
+}
+
+// tokens are listed by car id so pagination is stable
+fn page(mut cars: Vec<&Car>, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+    cars.sort_by(|a, b| a.car_id.cmp(&b.car_id));
+    let from_index: usize = from_index.map(|index| index.0 as usize).unwrap_or(0);
+    let limit: usize = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+    cars.into_iter().skip(from_index).take(limit).map(car_token).collect()
+}
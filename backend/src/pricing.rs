@@ -0,0 +1,72 @@
+// Pricing rules shared by the booking, rental and return flows.
+// All timestamps are in nanoseconds, all amounts in yoctoNEAR.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+pub const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+// number of started hours between two timestamps
+pub fn billable_hours(start_time: u64, end_time: u64) -> u64 {
+    if end_time <= start_time {
+        return 0;
+    }
+    (end_time - start_time).div_ceil(NANOS_PER_HOUR)
+}
+
+// price of a booking, billed per full hour
+pub fn rental_fee(hourly_rate: u128, start_time: u64, end_time: u64) -> u128 {
+    let rental_duration: u64 = end_time.saturating_sub(start_time) / NANOS_PER_HOUR;
+    (rental_duration as u128) * hourly_rate
+}
+
+// overtime charge for a car returned at `returned_at`; returns within the grace
+// period are free, later returns are billed for every started hour after `end_time`
+pub fn overtime_fee(end_time: u64, returned_at: u64, grace_period: u64, late_fee_rate: u128) -> u128 {
+    if returned_at <= end_time.saturating_add(grace_period) {
+        return 0;
+    }
+    billable_hours(end_time, returned_at) as u128 * late_fee_rate
+}
+
+// price of extending a booking from `end_time` to `new_end_time`
+pub fn extension_fee(hourly_rate: u128, end_time: u64, new_end_time: u64) -> u128 {
+    billable_hours(end_time, new_end_time) as u128 * hourly_rate
+}
+
+// pricing terms of a car as agreed when a booking is made, so later rate
+// changes by the owner never reprice a confirmed booking
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub struct PriceSnapshot {
+    pub hourly_rate: u128,
+    pub late_fee_rate: u128, // already falls back to hourly_rate when the car has none
+    pub grace_period: u64,
+    pub early_return_policy: EarlyReturnPolicy,
+    pub no_show_grace: u64,
+    pub no_show_fee: u128,
+    pub mileage_rate: u128, // per km driven
+}
+
+// how much of the rental fee is billed when a car is returned before end_time
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum EarlyReturnPolicy {
+    #[default]
+    NoRefund,
+    FullProRata, // only the started hours are billed
+    MinimumHours(u64), // started hours are billed, with a minimum number of hours
+}
+
+// part of the rental fee billed for a return at `returned_at`, the rest is refunded
+pub fn early_return_fee(rental_fee: u128, start_time: u64, end_time: u64, returned_at: u64, policy: EarlyReturnPolicy) -> u128 {
+    let booked_hours: u64 = billable_hours(start_time, end_time);
+    if returned_at >= end_time || booked_hours == 0 {
+        return rental_fee;
+    }
+    let used_hours: u64 = match policy {
+        EarlyReturnPolicy::NoRefund => return rental_fee,
+        EarlyReturnPolicy::FullProRata => billable_hours(start_time, returned_at),
+        EarlyReturnPolicy::MinimumHours(minimum) => billable_hours(start_time, returned_at).max(minimum),
+    };
+    rental_fee * used_hours.min(booked_hours) as u128 / booked_hours as u128
+}
@@ -0,0 +1,103 @@
+// Renter requirements owners can set on their cars, enforced by book_car and rent_car.
+use crate::*;
+use crate::credentials::CredentialRequirement;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct BookingRequirements {
+    pub min_rating_x100: u64, // minimum average rating multiplied by 100, 0 to disable
+    pub min_completed_rentals: u64,
+    pub min_license_age: u64, // in nanoseconds since the license was issued
+    pub manual_approval: bool, // only renters approved by the owner can book
+    pub approved_renters: Vec<String>,
+    pub required_credentials: Vec<CredentialRequirement>, // valid credentials renters must hold
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // set_booking_requirements lets owners restrict a car to experienced renters
+    #[handle_result]
+    pub fn set_booking_requirements(&mut self, car_id: String, min_rating_x100: u64, min_completed_rentals: u64, min_license_age: u64, manual_approval: bool) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::ApproveBookings)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.requirements.min_rating_x100 = min_rating_x100;
+        car.requirements.min_completed_rentals = min_completed_rentals;
+        car.requirements.min_license_age = min_license_age;
+        car.requirements.manual_approval = manual_approval;
+        log!("Event: BookingRequirementsUpdated, car_id: {}, min_rating_x100: {}, min_completed_rentals: {}, min_license_age: {}, manual_approval: {}", car_id, min_rating_x100, min_completed_rentals, min_license_age, manual_approval);
+        Ok(format!("Booking requirements of car '{}' updated", car_id))
+    }
+
+    // approve_renter lets a renter book a car that requires manual approval
+    #[handle_result]
+    pub fn approve_renter(&mut self, car_id: String, user_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::ApproveBookings)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        if !car.requirements.approved_renters.contains(&user_id) {
+            car.requirements.approved_renters.push(user_id.clone());
+        }
+        log!("Event: RenterApproved, car_id: {}, user: {}", car_id, user_id);
+        Ok(format!("Renter '{}' approved for car '{}'", user_id, car_id))
+    }
+
+    #[handle_result]
+    pub fn revoke_renter_approval(&mut self, car_id: String, user_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::ApproveBookings)?;
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.requirements.approved_renters.retain(|u| *u != user_id);
+        log!("Event: RenterApprovalRevoked, car_id: {}, user: {}", car_id, user_id);
+        Ok(format!("Approval of renter '{}' for car '{}' revoked", user_id, car_id))
+    }
+
+    // set_license_issued_at lets a verifier record when a user's driving license
+    // was issued, after checking the license itself
+    #[handle_result]
+    pub fn set_license_issued_at(&mut self, user_id: String, issued_at: u64) -> Result<String, Error> {
+        let caller: AccountId = predecessor_account_id();
+        if !self.verifiers.contains(&caller) {
+            return Err(Error::NotVerifier);
+        }
+        let user: &mut User = self.users.get_mut(&user_id).ok_or(Error::UserNotFound)?;
+        if issued_at > block_timestamp() {
+            return Err(Error::InvalidLicenseDate);
+        }
+        user.license_issued_at = issued_at;
+        log!("Event: LicenseDateUpdated, user: {}, issued_at: {}, verifier: {}", user_id, issued_at, caller);
+        Ok(format!("License issue date of '{}' updated", user_id))
+    }
+}
+
+impl CarSharing {
+    // make sure a renter meets every requirement of a car before booking or renting it
+    pub(crate) fn check_requirements(&self, car: &Car, user_id: &str) -> Result<(), Error> {
+        let requirements: &BookingRequirements = &car.requirements;
+        let user = self.users.get(user_id).ok_or(Error::UserNotFound)?;
+        // unpaid damage claims block every new booking
+        if user.debt > 0 {
+            return Err(Error::OutstandingDebt);
+        }
+        if requirements.min_rating_x100 > 0 && (user.rating.count == 0 || user.rating.average_x100() < requirements.min_rating_x100) {
+            return Err(Error::RatingTooLow);
+        }
+        if user.completed_rentals < requirements.min_completed_rentals {
+            return Err(Error::NotEnoughRentals);
+        }
+        if requirements.min_license_age > 0
+            && (user.license_issued_at == 0 || block_timestamp().saturating_sub(user.license_issued_at) < requirements.min_license_age)
+        {
+            return Err(Error::LicenseTooRecent);
+        }
+        if requirements.manual_approval && !requirements.approved_renters.iter().any(|u| u == user_id) {
+            return Err(Error::RenterNotApproved);
+        }
+        if requirements.required_credentials.iter().any(|r| !self.holds_credential(user_id, r.kind, r.min_value)) {
+            return Err(Error::MissingCredential);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+// Two-way ratings: after a completed booking the renter reviews the car and its
+// owner, and the owner reviews the renter, once per side.
+use crate::*;
+
+pub const MIN_RATING: u8 = 1;
+pub const MAX_RATING: u8 = 5;
+
+// aggregated score of a car or an account
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub struct Rating {
+    pub sum: u64,
+    pub count: u64,
+}
+impl Rating {
+    pub fn add(&mut self, rating: u8) {
+        self.sum += rating as u64;
+        self.count += 1;
+    }
+
+    // average rating multiplied by 100, 0 when there is no review yet
+    pub fn average_x100(&self) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        self.sum * 100 / self.count
+    }
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct Review {
+    pub booking_id: String,
+    pub author: String,
+    pub subject: String, // car id for reviews by renters, user id for reviews by owners
+    pub rating: u8,
+    pub comment: String,
+    pub created_at: u64,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // submit_review rates the other side of a completed booking: the renter rates
+    // the car and its owner, the owner rates the renter
+    #[handle_result]
+    pub fn submit_review(&mut self, booking_id: String, rating: u8, comment: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        if !(MIN_RATING..=MAX_RATING).contains(&rating) {
+            return Err(Error::InvalidRating);
+        }
+        let booking: Booking = self
+            .bookings
+            .get(&booking_id)
+            .or_else(|| self.archived_bookings.get(&booking_id))
+            .cloned()
+            .ok_or(Error::BookingNotFound)?;
+        let is_renter: bool = self.booking_party(&booking, &caller, Permission::Owner)?;
+        if booking.status != BookingStatus::Completed {
+            return Err(Error::InvalidBookingStatus);
+        }
+        if (is_renter && booking.renter_reviewed) || (!is_renter && booking.owner_reviewed) {
+            return Err(Error::AlreadyReviewed);
+        }
+        let owner_id: String = self.cars.get(&booking.car_id).map(|car| car.owner_id.clone()).unwrap_or_default();
+        let subject: String = if is_renter { booking.car_id.clone() } else { booking.user_id.clone() };
+        let review = Review {
+            booking_id: booking_id.clone(),
+            author: caller.clone(),
+            subject: subject.clone(),
+            rating,
+            comment,
+            created_at: block_timestamp(),
+        };
+        if is_renter {
+            if let Some(car) = self.cars.get_mut(&booking.car_id) {
+                car.rating.add(rating);
+            }
+            if let Some(owner) = self.owners.get_mut(&owner_id) {
+                owner.rating.add(rating);
+            }
+            self.car_reviews.entry(subject.clone()).or_default().push(review);
+        } else {
+            if let Some(user) = self.users.get_mut(&booking.user_id) {
+                user.rating.add(rating);
+            }
+            self.user_reviews.entry(subject.clone()).or_default().push(review);
+        }
+        let stored = match self.bookings.get_mut(&booking_id) {
+            Some(b) => b,
+            None => self.archived_bookings.get_mut(&booking_id).ok_or(Error::BookingNotFound)?,
+        };
+        if is_renter {
+            stored.renter_reviewed = true;
+        } else {
+            stored.owner_reviewed = true;
+        }
+        log!("Event: ReviewSubmitted, booking_id: {}, author: {}, subject: {}, rating: {}", booking_id, caller, subject, rating);
+        Ok(format!("Review of '{}' submitted", subject))
+    }
+
+    // read-only functions
+    pub fn get_car_reviews(&self, car_id: String, from_index: u64, limit: u64) -> Vec<Review> {
+        page(self.car_reviews.get(&car_id), from_index, limit)
+    }
+
+    pub fn get_user_reviews(&self, user_id: String, from_index: u64, limit: u64) -> Vec<Review> {
+        page(self.user_reviews.get(&user_id), from_index, limit)
+    }
+
+    #[handle_result]
+    pub fn get_car_rating(&self, car_id: String) -> Result<Rating, String> {
+        self.cars.get(&car_id).map(|car| car.rating).ok_or("Car not found".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_owner_rating(&self, owner_id: String) -> Result<Rating, String> {
+        self.owners.get(&owner_id).map(|owner| owner.rating).ok_or("Owner not found".to_string())
+    }
+
+    #[handle_result]
+    pub fn get_user_rating(&self, user_id: String) -> Result<Rating, String> {
+        self.users.get(&user_id).map(|user| user.rating).ok_or("User not found".to_string())
+    }
+}
+
+fn page(reviews: Option<&Vec<Review>>, from_index: u64, limit: u64) -> Vec<Review> {
+    reviews
+        .map(|reviews| reviews.iter().skip(from_index as usize).take(limit as usize).cloned().collect())
+        .unwrap_or_default()
+}
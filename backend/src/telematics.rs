@@ -0,0 +1,181 @@
+// Telematics oracle: each device installed in a car reports through its own
+// authorized account. The latest reading of a car bills the distance driven at
+// return and checks that the car was left in its return area.
+use crate::*;
+
+// characters of the base32 alphabet used by geohashes
+const GEOHASH_ALPHABET: &str = "0123456789bcdefghjkmnpqrstuvwxyz";
+const MAX_GEOHASH_LENGTH: usize = 12;
+
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct TelematicsDevice {
+    pub device_id: String,
+    pub reporter_id: String, // account the device reports with
+}
+#[derive(Default, Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, JsonSchema)]
+pub struct TelemetryReading {
+    pub device_id: String,
+    pub odometer: u64, // in km
+    pub fuel_level: u8, // fuel or battery level in percent
+    pub geohash: String,
+    pub timestamp: u64, // when the device took the reading
+    pub reported_at: u64,
+}
+
+#[near_bindgen]
+impl CarSharing {
+    // register_telematics_device authorizes the reporter account of a device
+    // installed in the car, registering a known device again replaces its reporter
+    #[handle_result]
+    pub fn register_telematics_device(&mut self, car_id: String, device_id: String, reporter_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        reporter_id.parse::<AccountId>().map_err(|_| Error::InvalidAccountId)?;
+        let devices: &mut Vec<TelematicsDevice> = self.telematics_devices.entry(car_id.clone()).or_default();
+        // one reporter account per device
+        if devices.iter().any(|d| d.reporter_id == reporter_id && d.device_id != device_id) {
+            return Err(Error::ReporterInUse);
+        }
+        devices.retain(|d| d.device_id != device_id);
+        devices.push(TelematicsDevice {
+            device_id: device_id.clone(),
+            reporter_id: reporter_id.clone(),
+        });
+        log!("Event: TelematicsDeviceRegistered, car_id: {}, device_id: {}, reporter_id: {}", car_id, device_id, reporter_id);
+        Ok(format!("Device '{}' of car '{}' reports as '{}'", device_id, car_id, reporter_id))
+    }
+
+    #[handle_result]
+    pub fn remove_telematics_device(&mut self, car_id: String, device_id: String) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::Owner)?;
+        let devices: &mut Vec<TelematicsDevice> = self.telematics_devices.get_mut(&car_id).ok_or(Error::DeviceNotFound)?;
+        let index: usize = devices.iter().position(|d| d.device_id == device_id).ok_or(Error::DeviceNotFound)?;
+        devices.remove(index);
+        log!("Event: TelematicsDeviceRemoved, car_id: {}, device_id: {}", car_id, device_id);
+        Ok(format!("Device '{}' removed from car '{}'", device_id, car_id))
+    }
+
+    // report_telemetry records a reading of a registered device; readings must
+    // come in order, never from the future, and the odometer never goes backwards
+    #[handle_result]
+    pub fn report_telemetry(&mut self, car_id: String, odometer: u64, fuel: u8, geohash: String, timestamp: u64) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        if !self.cars.contains_key(&car_id) {
+            return Err(Error::CarNotFound);
+        }
+        let device_id: String = self
+            .telematics_devices
+            .get(&car_id)
+            .and_then(|devices| devices.iter().find(|d| d.reporter_id == caller))
+            .map(|d| d.device_id.clone())
+            .ok_or(Error::Unauthorized)?;
+        let now: u64 = block_timestamp();
+        if fuel > 100 || timestamp > now {
+            return Err(Error::InvalidTelemetry);
+        }
+        if let Some(last) = self.telemetry.get(&car_id) {
+            if timestamp <= last.timestamp {
+                return Err(Error::InvalidTelemetry);
+            }
+            if odometer < last.odometer {
+                return Err(Error::InvalidOdometer);
+            }
+        }
+        if !is_geohash(&geohash) {
+            return Err(Error::InvalidGeohash);
+        }
+        self.capture_start_odometers(&car_id, odometer, timestamp);
+        log!("Event: TelemetryReported, car_id: {}, device_id: {}, odometer: {}, fuel_level: {}, geohash: {}, timestamp: {}", car_id, device_id, odometer, fuel, geohash, timestamp);
+        self.telemetry.insert(
+            car_id.clone(),
+            TelemetryReading {
+                device_id,
+                odometer,
+                fuel_level: fuel,
+                geohash,
+                timestamp,
+                reported_at: now,
+            },
+        );
+        Ok(format!("Telemetry of car '{}' recorded", car_id))
+    }
+
+    // set_mileage_policy sets the fee per km driven and the area, as a geohash
+    // prefix, where the car must be returned
+    #[handle_result]
+    pub fn set_mileage_policy(&mut self, car_id: String, mileage_rate: u128, return_area: Option<String>) -> Result<String, Error> {
+        let caller = predecessor_account_id().to_string();
+        let car = self.cars.get(&car_id).ok_or(Error::CarNotFound)?;
+        self.authorize(&caller, Scope::Car(car), Permission::SetRates)?;
+        if return_area.as_ref().is_some_and(|area| !is_geohash(area)) {
+            return Err(Error::InvalidGeohash);
+        }
+        let car: &mut Car = self.cars.get_mut(&car_id).ok_or(Error::CarNotFound)?;
+        car.mileage_rate = mileage_rate;
+        car.return_area = return_area.clone();
+        log!("Event: MileagePolicyUpdated, car_id: {}, mileage_rate: {}, return_area: {:?}", car_id, mileage_rate, return_area);
+        Ok(format!("Mileage policy of car '{}' updated", car_id))
+    }
+
+    // read-only functions
+    pub fn get_telematics_devices(&self, car_id: String) -> Vec<TelematicsDevice> {
+        self.telematics_devices.get(&car_id).cloned().unwrap_or_default()
+    }
+
+    pub fn get_latest_telemetry(&self, car_id: String) -> Option<TelemetryReading> {
+        self.telemetry.get(&car_id).cloned()
+    }
+}
+
+impl CarSharing {
+    // reservations start without anyone calling the contract, so the first
+    // reading taken after a booking started gives it its start reading: the
+    // previous reading when there is one, as the car has not moved since, else
+    // this one; bookings checked in without telemetry keep their report
+    fn capture_start_odometers(&mut self, car_id: &str, odometer: u64, timestamp: u64) {
+        let start_odometer: u64 = self.telemetry.get(car_id).map(|last| last.odometer).unwrap_or(odometer);
+        for booking in self.bookings.values_mut() {
+            if booking.car_id == car_id
+                && booking.status.is_open()
+                && booking.start_time <= timestamp
+                && booking.start_odometer.is_none()
+                && booking.check_in.is_none()
+            {
+                booking.start_odometer = Some(start_odometer);
+                log!("Event: StartOdometerCaptured, booking_id: {}, car_id: {}, odometer: {}", booking.booking_id, car_id, start_odometer);
+            }
+        }
+    }
+
+    // true when telematics shows the car moved since the booking's start reading
+    pub(crate) fn was_driven(&self, booking: &Booking) -> bool {
+        booking.start_odometer.is_some_and(|start| {
+            self.telemetry
+                .get(&booking.car_id)
+                .is_some_and(|reading| reading.timestamp >= booking.start_time && reading.odometer > start)
+        })
+    }
+
+    // km driven during a booking according to the latest reading, which must be
+    // in the car's return area; readings older than the booking say nothing
+    // about the trip, so without a fresh one nothing is billed or checked
+    pub(crate) fn trip_distance(&self, car: &Car, booking: &Booking) -> Result<u64, Error> {
+        let reading: &TelemetryReading = match self.telemetry.get(&car.car_id).filter(|reading| reading.timestamp >= booking.start_time) {
+            Some(reading) => reading,
+            None => return Ok(0),
+        };
+        if car.return_area.as_ref().is_some_and(|area| !reading.geohash.starts_with(area.as_str())) {
+            return Err(Error::WrongReturnLocation);
+        }
+        // a trip without a start reading is measured from the check-in report
+        let start_odometer: Option<u64> = booking.start_odometer.or(booking.check_in.as_ref().map(|report| report.odometer));
+        Ok(start_odometer.map(|start| reading.odometer.saturating_sub(start)).unwrap_or(0))
+    }
+}
+
+fn is_geohash(geohash: &str) -> bool {
+    !geohash.is_empty() && geohash.len() <= MAX_GEOHASH_LENGTH && geohash.chars().all(|c| GEOHASH_ALPHABET.contains(c))
+}
@@ -1,8 +1,16 @@
-use car_sharing::CarSharing;
-use near_sdk::{testing_env, AccountId, Gas};
+use car_sharing::batch::{BatchMode, CarInput, CarPatch};
+use car_sharing::co_ownership::{CarAction, CoOwner};
+use car_sharing::credentials::{CredentialKind, CredentialRequirement};
+use car_sharing::delegation::Permission;
+use car_sharing::disputes::DisputeStatus;
+use car_sharing::pricing::{self, EarlyReturnPolicy};
+use car_sharing::{BookingStatus, CarSharing, Error};
+use near_sdk::{testing_env, AccountId, CurveType, Gas, PublicKey};
+use near_sdk::json_types::Base64VecU8;
 use near_sdk::test_utils::{accounts, VMContextBuilder};
 use near_sdk::env::block_timestamp;
 use near_token::NearToken;
+use ed25519_dalek::{Signer, SigningKey};
 
 // Mocking the VM context for testing purposes
 fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
@@ -21,6 +29,17 @@ fn init_contract() -> CarSharing {
     CarSharing::init()
 }
 
+// Books a car with the deposit attached by the renter, then restores the
+// caller and block time of the current context
+fn book_car(contract: &mut CarSharing, car_id: String, user_id: String, start_time: u64, end_time: u64, deposit: NearToken) -> Result<String, Error> {
+    let caller: AccountId = near_sdk::env::predecessor_account_id();
+    let now: u64 = block_timestamp();
+    testing_env!(get_context(user_id.parse().unwrap()).block_timestamp(now).attached_deposit(deposit).build());
+    let result = contract.book_car(car_id, user_id, start_time, end_time, None);
+    testing_env!(get_context(caller).block_timestamp(now).build());
+    result
+}
+
 #[tokio::test]
 async fn test_car_sharing_initialization() {
     let contract = init_contract();
@@ -100,6 +119,36 @@ async fn test_delete_car() {
     assert!(contract.cars.get("car1").is_none(), "Car was not deleted");
 }
 
+#[tokio::test]
+async fn test_delete_car_with_bookings() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 2000000000000000000000).unwrap();
+    let now = near_sdk::env::block_timestamp();
+    book_car(&mut contract,
+        "car1".to_string(),
+        "user1".to_string(),
+        now,
+        now + 3600000000000,
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000),
+    ).unwrap();
+    // plain deletion is refused while the booking exists
+    assert!(contract.delete_car("car1".to_string()).is_err(), "Car with bookings should not be deleted");
+    assert!(contract.cars.contains_key("car1"), "Car should still exist");
+    contract.register_telematics_device("car1".to_string(), "tracker-1".to_string(), "tracker1".to_string()).unwrap();
+    // forced deletion cancels the booking with an owner-paid penalty
+    testing_env!(get_context("owner1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(10_000_000_000_000_000_000))
+        .build());
+    let result = contract.force_delete_car("car1".to_string());
+    assert!(result.is_ok(), "Force deleting car failed: {:?}", result.err());
+    assert!(contract.cars.get("car1").is_none(), "Car was not deleted");
+    assert_eq!(contract.bookings.len(), 0, "Bookings for the car should be cancelled");
+    assert!(contract.get_telematics_devices("car1".to_string()).is_empty(), "Devices of the deleted car should be removed");
+}
+
 #[tokio::test]
 async fn test_book_car() {
     let mut contract = init_contract();
@@ -114,12 +163,12 @@ async fn test_book_car() {
     // get current block timestamp
     let now = near_sdk::env::block_timestamp();
     // try to book the car
-    let result = contract.book_car(
+    let result = book_car(&mut contract,
         "car1".to_string(),
         "user1".to_string(),
         now, // Start time now
         now + 3600000000000, // One hour from now
-        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000), // 0.1 NEAR deposit
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000), // 0.1 NEAR deposit
     );
     // verify the result
     assert!(result.is_ok(), "Booking car failed: {:?}", result.err());
@@ -128,6 +177,14 @@ async fn test_book_car() {
         1,
         "Booking was not created"
     );
+    // the reservation deposit must actually be attached
+    let hour = 3600000000000;
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(2 * hour).build());
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 3 * hour, 4 * hour, near_sdk::NearToken::from_yoctonear(1000));
+    assert!(result.is_err(), "Booking without the deposit should fail");
+    // bookings cannot start in the past
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), hour, 4 * hour, near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000));
+    assert!(result.is_err(), "Booking in the past should fail");
 }
 
 #[tokio::test]
@@ -138,15 +195,17 @@ async fn test_cancel_booking() {
     contract.add_car("car1".to_string(), "owner1".to_string(), 2000000000000000000000).unwrap();
     
     let now = near_sdk::env::block_timestamp();
-    contract.book_car(
+    book_car(&mut contract,
         "car1".to_string(),
         "user1".to_string(),
         now,
         now + 3600000000000, // One hour from now
-        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000), // 0.1 NEAR deposit
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000), // 0.1 NEAR deposit
     ).unwrap();
     
     let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    assert!(contract.cancel_booking(booking_id.clone()).is_err(), "Only the renter can cancel a booking");
+    testing_env!(get_context("user1".parse().unwrap()).build());
     let result = contract.cancel_booking(booking_id);
     assert!(result.is_ok(), "Canceling booking failed");
     assert_eq!(contract.bookings.len(), 0, "Booking was not canceled");
@@ -166,14 +225,14 @@ async fn test_rent_car() {
         .attached_deposit(NearToken::from_yoctonear(2_000_000_000_000_000_000_000_000u128)) // Deposit equals 1-hour rent
         .build());
     // Attempt to rent the car for 1 hour
-    let result = contract.rent_car("car1".to_string(), "user1".to_string(), 1); // Rent for 1 hour
+    let result = contract.rent_car("car1".to_string(), "user1".to_string(), 1, None); // Rent for 1 hour
     // Verify the car rental result is successful
     assert!(result.is_ok(), "Renting car failed");
     // Verify that the car is no longer available after renting
     let car = contract.cars.get("car1").unwrap();
     assert!(!car.available, "Car should not be available after renting");
     // Check if a booking record was created with the correct details
-    let booking_id = format!("{}-{}-{}", "car1", "user1", block_timestamp());
+    let booking_id = contract.get_booking_id("car1".to_string(), "user1".to_string(), block_timestamp()).unwrap();
     let booking = contract.bookings.get(&booking_id);
     assert!(booking.is_some(), "Booking record was not created");
 
@@ -185,6 +244,32 @@ async fn test_rent_car() {
 }
 
 
+#[tokio::test]
+async fn test_booking_ids_are_unique() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("b-cd".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    contract.create_user_account("cd".to_string(), "Bob".to_string(), "DL-789012".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("a".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.add_car("a-b".to_string(), "owner1".to_string(), 1000).unwrap();
+    // both would have been "a-b-cd-0" with composite ids
+    book_car(&mut contract, "a".to_string(), "b-cd".to_string(), 0, 3600000000000, NearToken::from_yoctonear(900)).unwrap();
+    book_car(&mut contract, "a-b".to_string(), "cd".to_string(), 0, 3600000000000, NearToken::from_yoctonear(900)).unwrap();
+    assert_eq!(contract.bookings.len(), 2, "A booking was overwritten");
+    let first = contract.get_booking_id("a".to_string(), "b-cd".to_string(), 0).unwrap();
+    let second = contract.get_booking_id("a-b".to_string(), "cd".to_string(), 0).unwrap();
+    assert_ne!(first, second, "Booking ids should differ");
+
+    // re-booking the same car, user and start time after a cancellation gets a fresh id
+    testing_env!(get_context("b-cd".parse().unwrap()).build());
+    contract.cancel_booking(first.clone()).unwrap();
+    book_car(&mut contract, "a".to_string(), "b-cd".to_string(), 0, 3600000000000, NearToken::from_yoctonear(900)).unwrap();
+    let third = contract.get_booking_id("a".to_string(), "b-cd".to_string(), 0).unwrap();
+    assert!(third != first && third != second, "Booking ids should never be reused");
+    assert_eq!(contract.bookings.len(), 2, "A booking was overwritten");
+}
+
 #[tokio::test]
 async fn test_return_car() {
     let mut contract = init_contract();
@@ -195,14 +280,922 @@ async fn test_return_car() {
     
     // Simulate renting
     testing_env!(get_context(accounts(1)).prepaid_gas(Gas::from_gas(10u64.pow(12))).attached_deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000u128)).build());
-    contract.rent_car("car1".to_string(), "user1".to_string(), 1).unwrap();
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
     
     // Return the car
+    assert!(contract.return_car("car1".to_string()).is_err(), "Only the renter or the owner side can return the car");
+    testing_env!(get_context("user1".parse().unwrap()).build());
     let result = contract.return_car("car1".to_string());
     assert!(result.is_ok(), "Returning car failed");
     assert!(contract.cars.get("car1").unwrap().available, "Car should be available after return");
 }
 
+#[tokio::test]
+async fn test_late_return_with_top_up() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_late_return_policy("car1".to_string(), hour / 2, 1500).unwrap();
+    contract.set_security_deposit("car1".to_string(), 1000, 24).unwrap();
+
+    // rent for 2 hours, paying 500 more than the rental fee on top of the security deposit
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(3500))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 2, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    // return 1.5 hours late: 2 started hours of overtime at 1500 each
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(3 * hour + hour / 2).build());
+    contract.return_car("car1".to_string()).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.status, BookingStatus::Completed, "Late booking should be closed");
+    assert_eq!(booking.security_deposit, 0, "Shortfall should be taken from the security deposit first");
+    assert_eq!(booking.amount_due, 2000 + 3000 - 2500 - 1000, "Rest of the shortfall should be requested as a top-up");
+    assert_eq!(contract.users.get("user1").unwrap().debt, 1500, "Amount due should be recorded as debt");
+    assert!(contract.release_security_deposit(booking_id.clone()).is_err(), "Nothing is left to release");
+    assert!(contract.cars.get("car1").unwrap().available, "Car should be available after return");
+
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(3000))
+        .build());
+    contract.top_up_booking(booking_id.clone()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().amount_due, 0, "Top-up should clear the amount due");
+    assert_eq!(contract.users.get("user1").unwrap().debt, 0, "Top-up should clear the debt");
+}
+
+#[tokio::test]
+async fn test_early_return_pro_rated() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_early_return_policy("car1".to_string(), EarlyReturnPolicy::FullProRata).unwrap();
+
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(4000))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 4, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    // returned after 1.5 hours: 2 started hours are billed out of 4
+    assert_eq!(pricing::early_return_fee(4000, 0, 4 * hour, hour + hour / 2, EarlyReturnPolicy::FullProRata), 2000);
+    assert_eq!(pricing::early_return_fee(4000, 0, 4 * hour, hour + hour / 2, EarlyReturnPolicy::MinimumHours(3)), 3000);
+    assert_eq!(pricing::early_return_fee(4000, 0, 4 * hour, hour + hour / 2, EarlyReturnPolicy::NoRefund), 4000);
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(hour + hour / 2).build());
+    contract.return_car("car1".to_string()).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.returned_at, Some(hour + hour / 2), "Effective end time should be recorded");
+    assert_eq!(booking.amount_due, 0, "Nothing should be due after an early return");
+    assert_eq!(contract.get_car_calendar("car1".to_string())[0].2, hour + hour / 2, "Calendar should use the effective end time");
+}
+
+#[tokio::test]
+async fn test_extend_booking() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    contract.create_user_account("user2".to_string(), "Bob".to_string(), "DL-789012".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    book_car(&mut contract, "car1".to_string(), "user2".to_string(), 4 * hour, 5 * hour, NearToken::from_yoctonear(900)).unwrap();
+
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(2000))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 2, None).unwrap();
+    let booking_id = contract.bookings.values().find(|b| b.user_id == "user1").unwrap().booking_id.clone();
+
+    // extending over user2's booking is refused, extending up to it is allowed
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(3000))
+        .build());
+    assert!(contract.extend_booking(booking_id.clone(), 5 * hour).is_err(), "Extension should not overlap another booking");
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1000))
+        .build());
+    assert!(contract.extend_booking(booking_id.clone(), 4 * hour).is_err(), "Extension should be paid in full");
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(2000))
+        .build());
+    contract.extend_booking(booking_id.clone(), 4 * hour).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.end_time, 4 * hour, "End time was not extended");
+    assert_eq!(booking.rental_fee, 4000, "Extension was not charged");
+
+    // a booking past its end time and grace period can no longer be extended
+    let late_id = contract.bookings.values().find(|b| b.user_id == "user2").unwrap().booking_id.clone();
+    testing_env!(get_context("user2".parse().unwrap())
+        .block_timestamp(6 * hour)
+        .attached_deposit(NearToken::from_yoctonear(2000))
+        .build());
+    assert!(contract.extend_booking(late_id, 7 * hour).is_err(), "Overdue booking should not be extended");
+}
+
+#[tokio::test]
+async fn test_modify_booking() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_owner_account("owner2".to_string(), "Jane Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.add_car("car2".to_string(), "owner1".to_string(), 500).unwrap();
+    testing_env!(get_context("owner2".parse().unwrap()).build());
+    contract.add_car("car3".to_string(), "owner2".to_string(), 1000).unwrap();
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), hour, 3 * hour, NearToken::from_yoctonear(2000)).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(2000))
+        .build());
+    // cars of another owner are refused
+    assert!(contract.modify_booking(booking_id.clone(), Some("car3".to_string()), hour, 3 * hour).is_err(), "Car of another owner should be refused");
+    // reschedule to 4 hours, paying the difference
+    contract.modify_booking(booking_id.clone(), None, 2 * hour, 6 * hour).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.start_time, 2 * hour, "Start time was not moved");
+    assert_eq!(booking.rental_fee, 4000, "Booking was not re-priced");
+    assert_eq!(booking.history.len(), 1, "Previous terms should be kept");
+
+    // the new car's requirements and security deposit apply
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.set_booking_requirements("car2".to_string(), 0, 1, 0, false).unwrap();
+    contract.set_security_deposit("car2".to_string(), 300, 24).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    assert!(contract.modify_booking(booking_id.clone(), Some("car2".to_string()), 2 * hour, 6 * hour).is_err(), "Renter without rentals should be refused");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.set_booking_requirements("car2".to_string(), 0, 0, 0, false).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    assert!(contract.modify_booking(booking_id.clone(), Some("car2".to_string()), 2 * hour, 6 * hour).is_err(), "Security deposit of the new car should be paid");
+
+    // move to the cheaper car of the same owner, the escrow difference is refunded
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(300))
+        .build());
+    contract.modify_booking(booking_id.clone(), Some("car2".to_string()), 2 * hour, 6 * hour).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.car_id, "car2", "Booking was not moved to the new car");
+    assert_eq!(booking.deposit, 2000, "Escrow above the new price should be refunded");
+    assert_eq!(booking.security_deposit, 300, "Security deposit of the new car should be held");
+    assert_eq!(booking.history.len(), 2, "Previous terms should be kept");
+}
+
+#[tokio::test]
+async fn test_mark_no_show() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    contract.create_user_account("user2".to_string(), "Bob".to_string(), "DL-789012".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_no_show_policy("car1".to_string(), hour / 2, 500).unwrap();
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), hour, 3 * hour, NearToken::from_yoctonear(1800)).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    // still inside the grace period
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(hour + hour / 4).build());
+    assert!(contract.mark_no_show(booking_id.clone()).is_err(), "No-show should wait for the grace period");
+    // only the owner can mark a no-show
+    testing_env!(get_context("user2".parse().unwrap()).block_timestamp(2 * hour).build());
+    assert!(contract.mark_no_show(booking_id.clone()).is_err(), "Only the owner can mark a no-show");
+
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(2 * hour).build());
+    contract.mark_no_show(booking_id.clone()).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.status, BookingStatus::NoShow, "Booking should be marked as no-show");
+    assert_eq!(booking.deposit, 0, "Escrow should be released");
+    // the car can be booked again for the freed period
+    let result = book_car(&mut contract, "car1".to_string(), "user2".to_string(), 2 * hour, 3 * hour, NearToken::from_yoctonear(900));
+    assert!(result.is_ok(), "Car should be free after a no-show: {:?}", result.err());
+}
+
+#[tokio::test]
+async fn test_process_expired() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    // every booking pays the reward for its own cleanup
+    contract.set_cleanup_fee(10).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.set_cleanup_fee(0).is_err(), "Only the admin can set the cleanup fee");
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    // a reservation that is never picked up, a completed rental and a future reservation
+    assert!(book_car(&mut contract, "car1".to_string(), "user1".to_string(), 2 * hour, 3 * hour, NearToken::from_yoctonear(900)).is_err(), "The cleanup fee must be attached");
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), 2 * hour, 3 * hour, NearToken::from_yoctonear(910)).unwrap();
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), 100 * hour, 101 * hour, NearToken::from_yoctonear(910)).unwrap();
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1010))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    contract.return_car("car1".to_string()).unwrap();
+
+    // the completed rental waits for its claim window to close
+    testing_env!(get_context(accounts(2)).block_timestamp(5 * hour).build());
+    contract.process_expired(10).unwrap();
+    assert_eq!(contract.bookings.len(), 2, "Completed rental can still be claimed against");
+    testing_env!(get_context(accounts(2)).block_timestamp(80 * hour).build());
+    assert!(contract.process_expired(0).is_err(), "Empty batches should be refused");
+    contract.process_expired(2).unwrap();
+    contract.process_expired(2).unwrap();
+    assert_eq!(contract.bookings.len(), 1, "Only the future reservation should remain");
+    assert_eq!(contract.archived_bookings.len(), 2, "Expired bookings should be archived");
+    let archived = contract.archived_bookings.values().find(|b| b.start_time == 2 * hour).unwrap();
+    assert_eq!(archived.status, BookingStatus::NoShow, "Unused reservation should be settled as a no-show");
+    assert_eq!(archived.cleanup_fee, 0, "The cleanup fee should be paid out on archiving");
+    assert_eq!(contract.bookings.values().next().unwrap().cleanup_fee, 10, "Pending bookings keep their cleanup fee");
+    assert!(contract.get_booking(archived.booking_id.clone()).is_ok(), "Archived bookings should stay readable");
+}
+
+#[tokio::test]
+async fn test_dispute_resolution() {
+    let day: u64 = 24 * 3600000000000;
+    let mut contract = init_contract();
+    contract.add_arbitrator(accounts(2).to_string()).unwrap();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.add_arbitrator("owner1".to_string()).is_err(), "Only the admin can register arbitrators");
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.add_car("car2".to_string(), "owner1".to_string(), 1000).unwrap();
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), day, 3 * day, NearToken::from_yoctonear(43200)).unwrap();
+    book_car(&mut contract, "car2".to_string(), "user1".to_string(), day, 3 * day, NearToken::from_yoctonear(43200)).unwrap();
+    let first = contract.get_booking_id("car1".to_string(), "user1".to_string(), day).unwrap();
+    let second = contract.get_booking_id("car2".to_string(), "user1".to_string(), day).unwrap();
+
+    // a rental in progress cannot be disputed until the car is returned
+    contract.add_car("car3".to_string(), "owner1".to_string(), 1000).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).attached_deposit(NearToken::from_yoctonear(1000)).build());
+    contract.rent_car("car3".to_string(), "user1".to_string(), 1, None).unwrap();
+    let rental = contract.bookings.values().find(|b| b.car_id == "car3").unwrap().booking_id.clone();
+    assert!(contract.open_dispute(rental, "Car is dirty".to_string(), "hash-0".to_string()).is_err(), "Rental in progress cannot be disputed");
+
+    // the renter disputes both reservations, the escrow is frozen
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    contract.open_dispute(first.clone(), "Car was dirty".to_string(), "hash-1".to_string()).unwrap();
+    contract.open_dispute(second.clone(), "Car was late".to_string(), "hash-2".to_string()).unwrap();
+    assert_eq!(contract.bookings.get(&first).unwrap().status, BookingStatus::Disputed, "Booking should be frozen");
+    assert!(contract.cancel_booking(first.clone()).is_err(), "Disputed bookings cannot be cancelled");
+    assert_eq!(contract.get_dispute(first.clone()).unwrap().arbitrator, Some(accounts(2).to_string()), "Arbitrator should be assigned");
+
+    // the owner answers on the first dispute, the arbitrator splits it
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.submit_evidence(first.clone(), "hash-3".to_string()).unwrap();
+    assert!(contract.resolve_dispute(first.clone(), 5000).is_err(), "Only the arbitrator can rule");
+    testing_env!(get_context(accounts(2)).build());
+    contract.resolve_dispute(first.clone(), 5000).unwrap();
+    assert_eq!(contract.bookings.get(&first).unwrap().deposit, 0, "Escrow should be paid out");
+    assert_eq!(contract.get_dispute(first).unwrap().status, DisputeStatus::Resolved, "Dispute should be resolved");
+
+    // the owner never answers on the second one, the renter wins by default
+    assert!(contract.settle_dispute_by_default(second.clone()).is_err(), "Default outcome should wait for the deadline");
+    testing_env!(get_context(accounts(2)).block_timestamp(4 * day).build());
+    contract.settle_dispute_by_default(second.clone()).unwrap();
+    assert_eq!(contract.get_dispute(second).unwrap().renter_share_bps, Some(10000), "Renter should win by default");
+}
+
+#[tokio::test]
+async fn test_reviews() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1000))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    // reviews wait for the booking to be completed
+    assert!(contract.submit_review(booking_id.clone(), 5, "Great car".to_string()).is_err(), "Active bookings cannot be reviewed");
+    contract.return_car("car1".to_string()).unwrap();
+
+    assert!(contract.submit_review(booking_id.clone(), 6, "Great car".to_string()).is_err(), "Ratings are between 1 and 5");
+    contract.submit_review(booking_id.clone(), 4, "Great car".to_string()).unwrap();
+    assert!(contract.submit_review(booking_id.clone(), 5, "Again".to_string()).is_err(), "One review per side");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.submit_review(booking_id.clone(), 5, "Careful driver".to_string()).unwrap();
+
+    assert_eq!(contract.get_car_rating("car1".to_string()).unwrap().average_x100(), 400, "Car rating mismatch");
+    assert_eq!(contract.get_owner_rating("owner1".to_string()).unwrap().count, 1, "Owner rating mismatch");
+    assert_eq!(contract.get_user_rating("user1".to_string()).unwrap().average_x100(), 500, "User rating mismatch");
+    assert_eq!(contract.get_car_reviews("car1".to_string(), 0, 10).len(), 1, "Car reviews mismatch");
+    assert_eq!(contract.get_user_reviews("user1".to_string(), 1, 10).len(), 0, "Paging should skip reviews");
+}
+
+#[tokio::test]
+async fn test_booking_requirements() {
+    let year: u64 = 365 * 24 * 3600000000000;
+    let mut contract = init_contract();
+    contract.add_verifier("dmv".to_string()).unwrap();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(5 * year).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_booking_requirements("car1".to_string(), 0, 1, 3 * year, true).unwrap();
+
+    // no completed rental yet
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 6 * year, 6 * year + 3600000000000, NearToken::from_yoctonear(900));
+    assert!(result.is_err(), "Renter without rentals should be refused");
+    contract.users.get_mut("user1").unwrap().completed_rentals = 1;
+    // license is too recent, its issue date is recorded by a verifier
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(5 * year).build());
+    assert!(contract.set_license_issued_at("user1".to_string(), year).is_err(), "Renters cannot vouch for their own license");
+    testing_env!(get_context("dmv".parse().unwrap()).block_timestamp(5 * year).build());
+    contract.set_license_issued_at("user1".to_string(), 4 * year).unwrap();
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 6 * year, 6 * year + 3600000000000, NearToken::from_yoctonear(900));
+    assert!(result.is_err(), "Recent license should be refused");
+    contract.set_license_issued_at("user1".to_string(), year).unwrap();
+    // owner approval is still missing
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 6 * year, 6 * year + 3600000000000, NearToken::from_yoctonear(900));
+    assert!(result.is_err(), "Unapproved renter should be refused");
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(5 * year).build());
+    contract.approve_renter("car1".to_string(), "user1".to_string()).unwrap();
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 6 * year, 6 * year + 3600000000000, NearToken::from_yoctonear(900));
+    assert!(result.is_ok(), "Renter meeting every requirement should book: {:?}", result.err());
+}
+
+#[tokio::test]
+async fn test_security_deposit_release() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_security_deposit("car1".to_string(), 500, 24).unwrap();
+
+    // the rental payment alone is not enough
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1000))
+        .build());
+    assert!(contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).is_err(), "Security deposit should be required");
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1500))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.deposit, 1000, "Rental payment mismatch");
+    assert_eq!(booking.security_deposit, 500, "Security deposit mismatch");
+
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(hour).build());
+    contract.return_car("car1".to_string()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().security_deposit, 500, "Security deposit should be held after return");
+    testing_env!(get_context(accounts(2)).block_timestamp(12 * hour).build());
+    assert!(contract.release_security_deposit(booking_id.clone()).is_err(), "Security deposit should be held for 24 hours");
+    testing_env!(get_context(accounts(2)).block_timestamp(25 * hour).build());
+    contract.release_security_deposit(booking_id.clone()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().security_deposit, 0, "Security deposit should be released");
+}
+
+#[tokio::test]
+async fn test_damage_claim() {
+    let hour: u64 = 3600000000000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_security_deposit("car1".to_string(), 500, 24).unwrap();
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1500))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    contract.return_car("car1".to_string()).unwrap();
+
+    // the owner claims more than the security deposit
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(hour).build());
+    contract.file_damage_claim(booking_id.clone(), 800, "scratch-photos".to_string()).unwrap();
+    assert!(contract.file_damage_claim(booking_id.clone(), 100, "more".to_string()).is_err(), "One claim per booking");
+    testing_env!(get_context(accounts(2)).block_timestamp(25 * hour).build());
+    assert!(contract.release_security_deposit(booking_id.clone()).is_err(), "Claimed deposit should stay frozen");
+
+    // accepting pays the deposit and leaves the shortfall as a debt
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(25 * hour).build());
+    contract.accept_damage_claim(booking_id.clone()).unwrap();
+    assert_eq!(contract.users.get("user1").unwrap().debt, 300, "Shortfall should be recorded as debt");
+    let result = book_car(&mut contract, "car1".to_string(), "user1".to_string(), 30 * hour, 31 * hour, NearToken::from_yoctonear(900));
+    assert!(result.is_err(), "Renter with debt should not book");
+    testing_env!(get_context("user1".parse().unwrap())
+        .block_timestamp(25 * hour)
+        .attached_deposit(NearToken::from_yoctonear(300))
+        .build());
+    contract.pay_debt().unwrap();
+    assert_eq!(contract.users.get("user1").unwrap().debt, 0, "Debt should be paid");
+    assert_eq!(contract.get_damage_claim(booking_id).unwrap().outstanding, 0, "Claim should be fully paid");
+
+    // a claim the renter ignores is settled as accepted after its deadline;
+    // claims do not depend on the car holding the security deposit after return
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(25 * hour).build());
+    contract.set_security_deposit("car1".to_string(), 500, 0).unwrap();
+    testing_env!(get_context("user1".parse().unwrap())
+        .block_timestamp(25 * hour)
+        .attached_deposit(NearToken::from_yoctonear(1500))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().find(|b| b.status == BookingStatus::Active).unwrap().booking_id.clone();
+    contract.return_car("car1".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(26 * hour).build());
+    contract.file_damage_claim(booking_id.clone(), 700, "bumper-photos".to_string()).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(27 * hour).build());
+    assert!(contract.cancel_booking(booking_id.clone()).is_err(), "Completed bookings cannot be cancelled");
+    assert!(contract.settle_unanswered_claim(booking_id.clone()).is_err(), "Renter can still respond");
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(100 * hour).build());
+    assert!(contract.contest_damage_claim(booking_id.clone(), "Not me".to_string(), "hash".to_string()).is_err(), "Response deadline has passed");
+    contract.settle_unanswered_claim(booking_id.clone()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().security_deposit, 0, "Deposit should be paid out and released");
+
+    // the debt still pays the owner once the car is gone
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(100 * hour).build());
+    contract.delete_car("car1".to_string()).unwrap();
+    let earned = contract.get_owner_earnings("owner1".to_string());
+    testing_env!(get_context("user1".parse().unwrap())
+        .block_timestamp(100 * hour)
+        .attached_deposit(NearToken::from_yoctonear(200))
+        .build());
+    contract.pay_debt().unwrap();
+    assert_eq!(contract.get_owner_earnings("owner1".to_string()), earned + 200, "Payout should reach the owner of the deleted car");
+}
+
+#[tokio::test]
+async fn test_insurance_pool() {
+    let mut contract = init_contract();
+    // admin defines a tier: 10% premium, 100 deductible, up to 1000 covered
+    contract.set_insurance_tier("basic".to_string(), 1000, 100, 1000).unwrap();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_security_deposit("car1".to_string(), 500, 24).unwrap();
+    // coverage is chosen when renting, the premium is paid on top
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(2700))
+        .build());
+    assert!(contract.rent_car("car1".to_string(), "user1".to_string(), 2, Some("gold".to_string())).is_err(), "Unknown tiers should be refused");
+    contract.rent_car("car1".to_string(), "user1".to_string(), 2, Some("basic".to_string())).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().deposit, 2000, "Premium should not be held as escrow");
+    let pool = contract.get_insurance_pool();
+    assert_eq!(pool.balance, 200, "Premium should flow into the pool");
+    assert_eq!(pool.active_coverage, 1000, "Coverage of the booking should be reported");
+    assert_eq!(pool.solvency_bps, 2000, "Pool covers 20% of its exposure");
+
+    contract.return_car("car1".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.file_damage_claim(booking_id.clone(), 250, "dent-photos".to_string()).unwrap();
+    // the renter pays the deductible from the deposit, the part above it waits for approval
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    contract.accept_damage_claim(booking_id.clone()).unwrap();
+    assert_eq!(contract.get_damage_claim(booking_id.clone()).unwrap().pending_coverage, 150, "Covered part should await approval");
+    assert_eq!(contract.get_insurance_pool().balance, 200, "Nothing is paid before approval");
+    assert_eq!(contract.users.get("user1").unwrap().debt, 0, "Deductible is covered by the deposit");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.review_insurance_payout(booking_id.clone(), true).is_err(), "Owners cannot approve their own payout");
+    testing_env!(get_context(accounts(0)).build());
+    contract.review_insurance_payout(booking_id.clone(), true).unwrap();
+    let pool = contract.get_insurance_pool();
+    assert_eq!(pool.balance, 50, "Pool should pay 150 above the deductible");
+    assert_eq!(pool.claims_paid, 150, "Payout should be reported");
+}
+
+#[tokio::test]
+async fn test_car_nft_transfer() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    assert_eq!(contract.nft_token("car1".to_string()).unwrap().owner_id, "owner1", "Car should be minted to its owner");
+    let now = block_timestamp();
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), now, now + pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    contract.register_telematics_device("car1".to_string(), "obd-1".to_string(), "tracker1".to_string()).unwrap();
+    contract.register_lock("car1".to_string(), "lock-42".to_string(), "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".parse().unwrap()).unwrap();
+
+    // a transfer needs exactly one yoctoNEAR attached
+    assert!(contract.nft_transfer("owner2".parse().unwrap(), "car1".to_string(), None, None).is_err(), "Transfer without 1 yocto should fail");
+    testing_env!(get_context("owner1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1))
+        .build());
+    contract.nft_transfer("owner2".parse().unwrap(), "car1".to_string(), None, Some("sold".to_string())).unwrap();
+    assert_eq!(contract.nft_token("car1".to_string()).unwrap().owner_id, "owner2", "Token should belong to the new owner");
+    assert_eq!(contract.list_owner_cars("owner2".to_string()).unwrap().len(), 1, "Listing should move to the new owner");
+    assert_eq!(contract.nft_tokens_for_owner("owner1".parse().unwrap(), None, None).len(), 0, "Old owner should hold no token");
+    // the existing booking is kept and still pays the old owner
+    let booking = contract.get_booking(booking_id).unwrap();
+    assert_eq!(booking.owner_id, "owner1", "Pending payout should go to the old owner");
+    assert!(contract.nft_transfer("owner3".parse().unwrap(), "car1".to_string(), None, None).is_err(), "Old owner can no longer transfer");
+    // the old owner's devices and lock key go with the sale
+    assert!(contract.get_telematics_devices("car1".to_string()).is_empty(), "Old reporters should be removed");
+    assert!(contract.cars.get("car1").unwrap().lock_authority_key.is_none(), "Old lock key should be removed");
+
+    // damage on the booking is claimed by the owner it was made with
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(pricing::NANOS_PER_HOUR / 2).build());
+    contract.return_car("car1".to_string()).unwrap();
+    testing_env!(get_context("owner2".parse().unwrap()).block_timestamp(pricing::NANOS_PER_HOUR).build());
+    assert!(contract.file_damage_claim(booking_id.clone(), 100, "photos".to_string()).is_err(), "New owner cannot claim on an old booking");
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(pricing::NANOS_PER_HOUR).build());
+    contract.file_damage_claim(booking_id, 100, "photos".to_string()).unwrap();
+}
+
+#[tokio::test]
+async fn test_booking_transfer() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    contract.create_user_account("user2".to_string(), "Bob".to_string(), "DL-789012".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_booking_requirements("car1".to_string(), 0, 0, 0, true).unwrap();
+    contract.approve_renter("car1".to_string(), "user1".to_string()).unwrap();
+    let start = block_timestamp() + pricing::NANOS_PER_HOUR;
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), start, start + pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).unwrap();
+    let booking_id = contract.get_booking_id("car1".to_string(), "user1".to_string(), start).unwrap();
+
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    assert!(contract.transfer_booking(booking_id.clone(), "user2".to_string()).is_err(), "Booking must be tokenized first");
+    contract.mint_booking_token(booking_id.clone()).unwrap();
+    // the recipient has to meet the car's requirements
+    assert!(contract.transfer_booking(booking_id.clone(), "user2".to_string()).is_err(), "Unapproved recipient should be rejected");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.approve_renter("car1".to_string(), "user2".to_string()).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).build());
+    contract.transfer_booking(booking_id.clone(), "user2".to_string()).unwrap();
+    assert_eq!(contract.get_booking_token(booking_id.clone()).unwrap().owner_id, "user2", "Token should belong to the recipient");
+    assert_eq!(contract.get_booking_id("car1".to_string(), "user2".to_string(), start).unwrap(), booking_id, "Lookup key should follow the renter");
+
+    // owners can turn transfers off
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.set_booking_transfers("car1".to_string(), false).unwrap();
+    testing_env!(get_context("user2".parse().unwrap()).build());
+    assert!(contract.transfer_booking(booking_id, "user1".to_string()).is_err(), "Transfers should be disabled");
+}
+
+#[tokio::test]
+async fn test_driver_credentials() {
+    let mut contract = init_contract();
+    contract.add_verifier("dmv".to_string()).unwrap();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_required_credentials("car1".to_string(), vec![CredentialRequirement { kind: CredentialKind::YearsDriving, min_value: 3 }]).unwrap();
+    let now = block_timestamp();
+    assert!(book_car(&mut contract, "car1".to_string(), "user1".to_string(), now, now + pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).is_err(), "Renter without credential should be rejected");
+    assert!(contract.issue_credential("user1".to_string(), CredentialKind::YearsDriving, 5, 0).is_err(), "Only verifiers can issue credentials");
+
+    testing_env!(get_context("dmv".parse().unwrap()).build());
+    contract.issue_credential("user1".to_string(), CredentialKind::YearsDriving, 5, now + 24 * pricing::NANOS_PER_HOUR).unwrap();
+    assert!(contract.has_credential("user1".to_string(), CredentialKind::YearsDriving, 3), "Credential should be valid");
+    let issuer: AccountId = near_sdk::env::current_account_id();
+    assert_eq!(contract.sbt_supply_by_owner("user1".parse().unwrap(), issuer.clone(), None), 1, "User should hold one credential");
+    assert_eq!(contract.sbt_supply_by_class(issuer.clone(), CredentialKind::YearsDriving.class()), 1);
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), now, now + pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).unwrap();
+
+    // revoked credentials no longer count
+    let tokens = contract.sbt_tokens_by_owner("user1".parse().unwrap(), Some(issuer.clone()), None, None, None);
+    assert_eq!(tokens[0].0, issuer, "Tokens are grouped by issuer");
+    assert_eq!(tokens[0].1[0].metadata.class, CredentialKind::YearsDriving.class());
+    let token = tokens[0].1[0].token;
+    assert_eq!(contract.sbt(issuer.clone(), token).unwrap().owner.as_str(), "user1");
+    contract.revoke_credential(token.to_string()).unwrap();
+    assert!(contract.sbt_revoked(issuer.clone(), token), "Credential should be revoked");
+    assert!(contract.sbt_tokens_by_owner("user1".parse().unwrap(), None, None, None, None).is_empty(), "Revoked credential counts as expired");
+    assert_eq!(contract.sbt_tokens_by_owner("user1".parse().unwrap(), None, None, None, Some(true))[0].1.len(), 1);
+    assert!(!contract.has_credential("user1".to_string(), CredentialKind::YearsDriving, 3), "Revoked credential should be invalid");
+    let start = now + 2 * pricing::NANOS_PER_HOUR;
+    assert!(book_car(&mut contract, "car1".to_string(), "user1".to_string(), start, start + pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).is_err(), "Revoked credential should block bookings");
+}
+
+#[tokio::test]
+async fn test_co_owned_car() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    let co_owners = vec![
+        CoOwner { account_id: "owner1".to_string(), share_bps: 6000 },
+        CoOwner { account_id: "owner2".to_string(), share_bps: 3000 },
+        CoOwner { account_id: "owner3".to_string(), share_bps: 1000 },
+    ];
+    assert!(contract.set_co_owners("car1".to_string(), co_owners.clone(), None).is_err(), "Co-owned car needs a manager");
+    contract.set_co_owners("car1".to_string(), co_owners, Some("fleet".to_string())).unwrap();
+    // the manager runs the car, the listing owner alone no longer can
+    assert!(contract.set_late_return_policy("car1".to_string(), 0, 2000).is_err(), "Co-owned car is run by its manager");
+    testing_env!(get_context("fleet".parse().unwrap()).build());
+    contract.set_late_return_policy("car1".to_string(), 0, 2000).unwrap();
+    // other changes need a majority of the shares
+    testing_env!(get_context("owner2".parse().unwrap()).build());
+    contract.propose_car_change("car1".to_string(), CarAction::SetHourlyRate(2000)).unwrap();
+    let proposal_id = contract.next_proposal_id.to_string();
+    testing_env!(get_context("owner3".parse().unwrap()).build());
+    contract.approve_car_change(proposal_id.clone()).unwrap();
+    assert_eq!(contract.cars.get("car1").unwrap().hourly_rate, 1000, "40% of the shares is not a majority");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.approve_car_change(proposal_id).unwrap();
+    assert_eq!(contract.cars.get("car1").unwrap().hourly_rate, 2000, "Rate should change once approved");
+
+    // payouts are split by share
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(2000))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    contract.return_car("car1".to_string()).unwrap();
+    let earnings = contract.get_car_earnings("car1".to_string()).unwrap();
+    assert_eq!(earnings.iter().map(|e| e.earned).collect::<Vec<_>>(), vec![1200, 600, 200], "Payout should follow the shares");
+    assert_eq!(contract.get_owner_earnings("owner2".to_string()), 600, "Co-owner earnings mismatch");
+}
+
+#[tokio::test]
+async fn test_fleet_manager_delegation() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    testing_env!(get_context("fleet".parse().unwrap()).build());
+    assert!(contract.add_car("car1".to_string(), "owner1".to_string(), 1000).is_err(), "Manager needs a grant first");
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.grant_permissions("fleet".to_string(), vec![Permission::Owner]).is_err(), "Ownership cannot be delegated");
+    contract.grant_permissions("fleet".to_string(), vec![Permission::AddCars, Permission::SetRates]).unwrap();
+
+    testing_env!(get_context("fleet".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    assert_eq!(contract.cars.get("car1").unwrap().owner_id, "owner1", "Car should belong to the owner");
+    contract.set_late_return_policy("car1".to_string(), 0, 1500).unwrap();
+    assert!(contract.delete_car("car1".to_string()).is_err(), "Removing cars was not granted");
+
+    // revoking a permission takes effect immediately
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.revoke_permissions("fleet".to_string(), Some(vec![Permission::SetRates])).unwrap();
+    assert_eq!(contract.get_permissions("owner1".to_string(), "fleet".to_string()), vec![Permission::AddCars], "Only AddCars should remain");
+    testing_env!(get_context("fleet".parse().unwrap()).build());
+    assert!(contract.set_late_return_policy("car1".to_string(), 0, 2000).is_err(), "Revoked permission should be refused");
+}
+
+#[tokio::test]
+async fn test_batch_add_and_update_cars() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    let input = |car_id: &str, hourly_rate: u128| CarInput { car_id: car_id.to_string(), owner_id: "owner1".to_string(), hourly_rate };
+
+    // one invalid item stops an atomic batch
+    let results = contract.add_cars(vec![input("car1", 1000), input("car2", 0)], BatchMode::Atomic).unwrap();
+    assert!(results.iter().all(|r| !r.success), "Atomic batch should not apply anything");
+    assert!(contract.cars.is_empty(), "No car should be listed");
+
+    // best effort applies the valid items and reports the others
+    let results = contract.add_cars(vec![input("car1", 1000), input("car1", 1000), input("car2", 0), input("car3", 3000)], BatchMode::BestEffort).unwrap();
+    assert_eq!(results.iter().map(|r| r.success).collect::<Vec<_>>(), vec![true, false, false, true], "Per-item results mismatch");
+    assert_eq!(results[1].message, "Car already exists", "Duplicate should be reported");
+    assert_eq!(contract.cars.len(), 2, "Two cars should be listed");
+
+    let patch = CarPatch { car_id: "car1".to_string(), hourly_rate: Some(1500), no_show_fee: Some(200), ..Default::default() };
+    let missing = CarPatch { car_id: "car9".to_string(), hourly_rate: Some(1500), ..Default::default() };
+    let results = contract.update_cars(vec![patch, missing], BatchMode::BestEffort).unwrap();
+    assert!(results[0].success && !results[1].success, "Only the listed car can be updated");
+    assert_eq!(contract.cars.get("car1").unwrap().hourly_rate, 1500, "Rate should be updated");
+    assert_eq!(contract.cars.get("car1").unwrap().no_show_fee, 200, "No-show fee should be updated");
+    assert!(contract.add_cars(Vec::new(), BatchMode::Atomic).is_err(), "Empty batch should be rejected");
+
+    // best effort applies what fits in the gas even when the whole batch does not
+    testing_env!(get_context("owner1".parse().unwrap()).prepaid_gas(Gas::from_gas(6_500_000_000_000)).build());
+    let batch = vec![input("car4", 1000), input("car5", 1000), input("car6", 1000)];
+    assert!(contract.add_cars(batch.clone(), BatchMode::Atomic).is_err(), "Atomic batch needs gas for every item");
+    let results = contract.add_cars(batch, BatchMode::BestEffort).unwrap();
+    assert!(results[0].success, "First item fits in the gas");
+    assert_eq!(contract.cars.len(), 2 + results.iter().filter(|r| r.success).count(), "Applied items should be listed");
+    // an item that does not fit is skipped with everything after it
+    testing_env!(get_context("owner1".parse().unwrap()).prepaid_gas(Gas::from_tgas(5)).build());
+    let results = contract.add_cars(vec![input("car7", 1000), input("car8", 1000)], BatchMode::BestEffort).unwrap();
+    assert!(results.iter().all(|r| !r.success && r.message == "Skipped, not enough gas left"), "Every item after the first skip should be skipped");
+    assert!(!contract.cars.contains_key("car7"), "No car should be added");
+}
+
+#[tokio::test]
+async fn test_rate_update_keeps_booked_price() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1000))
+        .build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.update_hourly_rate("car1".to_string(), 0).is_err(), "Zero rate should be rejected");
+    contract.update_hourly_rate("car1".to_string(), 5000).unwrap();
+    assert_eq!(contract.cars.get("car1").unwrap().hourly_rate, 5000, "New rate should apply to the car");
+    // the confirmed booking keeps its agreed rate, extensions included
+    testing_env!(get_context("user1".parse().unwrap())
+        .attached_deposit(NearToken::from_yoctonear(1000))
+        .build());
+    let booking = contract.get_booking(booking_id.clone()).unwrap();
+    contract.extend_booking(booking_id.clone(), booking.end_time + pricing::NANOS_PER_HOUR).unwrap();
+    let booking = contract.get_booking(booking_id).unwrap();
+    assert_eq!(booking.pricing.hourly_rate, 1000, "Booked rate should be kept");
+    assert_eq!(booking.rental_fee, 2000, "Extension should be priced at the booked rate");
+}
+
+#[tokio::test]
+async fn test_maintenance() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    let start = block_timestamp() + pricing::NANOS_PER_HOUR;
+    contract.schedule_maintenance("car1".to_string(), start, start + 4 * pricing::NANOS_PER_HOUR, "brakes".to_string()).unwrap();
+    // bookings cannot overlap the maintenance window
+    assert!(book_car(&mut contract, "car1".to_string(), "user1".to_string(), start + pricing::NANOS_PER_HOUR, start + 2 * pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).is_err(), "Booking during maintenance should fail");
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), start + 4 * pricing::NANOS_PER_HOUR, start + 5 * pricing::NANOS_PER_HOUR, NearToken::from_yoctonear(900)).unwrap();
+
+    // the service log resets the service-due flag
+    contract.set_service_interval("car1".to_string(), 10000, 0).unwrap();
+    contract.record_service("car1".to_string(), 15000, "oil change".to_string(), "invoice-1".to_string()).unwrap();
+    assert!(contract.record_service("car1".to_string(), 14000, "tyres".to_string(), "invoice-2".to_string()).is_err(), "Odometer cannot go backwards");
+    let status = contract.get_service_status("car1".to_string()).unwrap();
+    assert!(!status.service_due, "Car was just serviced");
+    contract.set_service_interval("car1".to_string(), 10000, 1).unwrap();
+    assert!(contract.list_cars_due_for_service("owner1".to_string()).is_empty(), "No time has passed since the service");
+    testing_env!(get_context("owner1".parse().unwrap())
+        .block_timestamp(block_timestamp() + 1)
+        .build());
+    assert_eq!(contract.list_cars_due_for_service("owner1".to_string()).len(), 1, "Service interval has passed");
+}
+
+#[tokio::test]
+async fn test_keyless_access_grant() {
+    let hour: u64 = 3600000000000;
+    let key = "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp";
+    // key provisioned in the lock, held by the owner side to sign grants
+    let authority = SigningKey::from_bytes(&[7u8; 32]);
+    let authority_key = PublicKey::from_parts(CurveType::ED25519, authority.verifying_key().to_bytes().to_vec()).unwrap();
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.register_lock("car1".to_string(), "lock-42".to_string(), authority_key).unwrap();
+
+    testing_env!(get_context("user1".parse().unwrap()).attached_deposit(NearToken::from_yoctonear(1000)).build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+    let message = contract.get_access_grant_message(booking_id.clone(), key.to_string()).unwrap();
+    // grants must be signed by the key the lock trusts
+    let forged = SigningKey::from_bytes(&[9u8; 32]).sign(message.as_bytes()).to_bytes().to_vec();
+    assert!(contract.issue_access_grant(booking_id.clone(), key.parse().unwrap(), Base64VecU8::from(forged)).is_err(), "Grant not signed by the lock authority");
+    let signature = authority.sign(message.as_bytes()).to_bytes().to_vec();
+    contract.issue_access_grant(booking_id.clone(), key.parse().unwrap(), Base64VecU8::from(signature.clone())).unwrap();
+    assert!(contract.is_key_authorized("car1".to_string(), "lock-42".to_string(), key.to_string()), "Renter key should open the car");
+    assert!(!contract.is_key_authorized("car1".to_string(), "lock-7".to_string(), key.to_string()), "Grant is bound to the registered lock");
+
+    // only the renter can get a grant
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    assert!(contract.issue_access_grant(booking_id.clone(), key.parse().unwrap(), Base64VecU8::from(signature)).is_err(), "Owner is not the renter");
+
+    // extending the booking extends the grant
+    testing_env!(get_context("user1".parse().unwrap()).attached_deposit(NearToken::from_yoctonear(1000)).build());
+    contract.extend_booking(booking_id.clone(), 2 * hour).unwrap();
+    assert_eq!(contract.get_access_grant(booking_id.clone()).unwrap().valid_until, 2 * hour, "Grant should follow the extension");
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(hour + hour / 2).build());
+    assert!(contract.is_key_authorized("car1".to_string(), "lock-42".to_string(), key.to_string()), "Key should open the car after the original end");
+
+    // returning the car ends the grant
+    contract.return_car("car1".to_string()).unwrap();
+    assert!(!contract.is_key_authorized("car1".to_string(), "lock-42".to_string(), key.to_string()), "Grant ends with the booking");
+    assert!(contract.get_access_grant(booking_id).is_none());
+}
+
+#[tokio::test]
+async fn test_telematics_mileage_and_return_area() {
+    let minute: u64 = 60_000_000_000;
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 1000).unwrap();
+    contract.set_mileage_policy("car1".to_string(), 10, Some("u09".to_string())).unwrap();
+    contract.register_telematics_device("car1".to_string(), "obd-1".to_string(), "tracker1".to_string()).unwrap();
+    let start = block_timestamp();
+
+    // only the registered reporter can report
+    assert!(contract.report_telemetry("car1".to_string(), 1000, 80, "u09tun".to_string(), start).is_err(), "Owner is not a reporter");
+    testing_env!(get_context("tracker1".parse().unwrap()).build());
+    contract.report_telemetry("car1".to_string(), 1000, 80, "u09tun".to_string(), start).unwrap();
+
+    testing_env!(get_context("user1".parse().unwrap()).attached_deposit(NearToken::from_yoctonear(2000)).build());
+    contract.rent_car("car1".to_string(), "user1".to_string(), 1, None).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    // readings must move forward in time and distance
+    testing_env!(get_context("tracker1".parse().unwrap()).block_timestamp(start + 30 * minute).build());
+    assert!(contract.report_telemetry("car1".to_string(), 1050, 60, "u0bxyz".to_string(), start).is_err(), "Reading is not newer");
+    assert!(contract.report_telemetry("car1".to_string(), 990, 60, "u0bxyz".to_string(), start + minute).is_err(), "Odometer cannot go backwards");
+    assert!(contract.report_telemetry("car1".to_string(), 1050, 60, "u0bxyz".to_string(), start + 40 * minute).is_err(), "Reading cannot come from the future");
+    contract.report_telemetry("car1".to_string(), 1050, 60, "u0bxyz".to_string(), start + 20 * minute).unwrap();
+
+    // the car must be back in its return area
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start + 30 * minute).build());
+    assert!(contract.return_car("car1".to_string()).is_err(), "Car is outside its return area");
+    testing_env!(get_context("tracker1".parse().unwrap()).block_timestamp(start + 40 * minute).build());
+    contract.report_telemetry("car1".to_string(), 1060, 55, "u09tuq".to_string(), start + 40 * minute).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start + 45 * minute).build());
+    contract.return_car("car1".to_string()).unwrap();
+    let booking = contract.bookings.get(&booking_id).unwrap();
+    assert_eq!(booking.distance, 60, "Distance comes from telemetry");
+    assert_eq!(booking.fees, 600, "Distance is billed at the mileage rate");
+    assert_eq!(contract.get_service_status("car1".to_string()).unwrap().odometer, 1060);
+
+    // a reservation returned without check-in is measured from the last reading before it started
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start + 50 * minute).build());
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), start + 60 * minute, start + 120 * minute, NearToken::from_yoctonear(2000)).unwrap();
+    let reservation_id = contract.bookings.keys().find(|id| **id != booking_id).unwrap().clone();
+    testing_env!(get_context("tracker1".parse().unwrap()).block_timestamp(start + 55 * minute).build());
+    contract.report_telemetry("car1".to_string(), 1070, 55, "u09tuq".to_string(), start + 55 * minute).unwrap();
+    assert_eq!(contract.bookings.get(&reservation_id).unwrap().start_odometer, None, "Reservation has not started yet");
+    testing_env!(get_context("tracker1".parse().unwrap()).block_timestamp(start + 90 * minute).build());
+    contract.report_telemetry("car1".to_string(), 1100, 50, "u09tuq".to_string(), start + 90 * minute).unwrap();
+    assert_eq!(contract.bookings.get(&reservation_id).unwrap().start_odometer, Some(1070), "Start reading comes from the last reading before the trip");
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start + 100 * minute).build());
+    contract.return_car("car1".to_string()).unwrap();
+    let reservation = contract.bookings.get(&reservation_id).unwrap();
+    assert_eq!(reservation.distance, 30, "Distance of the reservation is billed");
+    assert_eq!(reservation.fees, 300);
+
+    // a driven reservation returned late is no no-show, it is settled with overtime
+    book_car(&mut contract, "car1".to_string(), "user1".to_string(), start + 130 * minute, start + 190 * minute, NearToken::from_yoctonear(2000)).unwrap();
+    let late_id = contract.bookings.keys().find(|id| **id != booking_id && **id != reservation_id).unwrap().clone();
+    testing_env!(get_context("tracker1".parse().unwrap()).block_timestamp(start + 160 * minute).build());
+    contract.report_telemetry("car1".to_string(), 1120, 45, "u09tuq".to_string(), start + 150 * minute).unwrap();
+    contract.report_telemetry("car1".to_string(), 1150, 40, "u09tuq".to_string(), start + 160 * minute).unwrap();
+    testing_env!(get_context("user2".parse().unwrap()).block_timestamp(start + 250 * minute).build());
+    contract.process_expired(10).unwrap();
+    assert_eq!(contract.bookings.get(&late_id).unwrap().status, BookingStatus::Reserved, "Driven reservation is not a no-show");
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start + 250 * minute).build());
+    contract.return_car("car1".to_string()).unwrap();
+    let late = contract.bookings.get(&late_id).unwrap();
+    assert_eq!(late.status, BookingStatus::Completed, "Late reservation is settled at return");
+    assert_eq!(late.distance, 50);
+    assert_eq!(late.fees, 1500, "Distance and one hour of overtime are billed");
+}
+
+#[tokio::test]
+async fn test_check_in_and_check_out() {
+    let mut contract = init_contract();
+    contract.create_owner_account("owner1".to_string(), "John Doe".to_string()).unwrap();
+    contract.create_user_account("user1".to_string(), "Alice".to_string(), "DL-123456".to_string()).unwrap();
+    testing_env!(get_context("owner1".parse().unwrap()).build());
+    contract.add_car("car1".to_string(), "owner1".to_string(), 2000000000000000000000).unwrap();
+    let now = near_sdk::env::block_timestamp();
+    let start = now + 3600000000000;
+    book_car(&mut contract,
+        "car1".to_string(),
+        "user1".to_string(),
+        start,
+        start + 3600000000000,
+        near_sdk::NearToken::from_yoctonear(2_000_000_000_000_000_000_000),
+    ).unwrap();
+    let booking_id = contract.bookings.values().next().unwrap().booking_id.clone();
+
+    // no check-in before the booking starts
+    assert!(contract.check_in(booking_id.clone(), 12000, 80, "photos-in".to_string()).is_err(), "Check-in should wait for the start time");
+
+    // owner reports, the rental only starts once the renter co-signs the same values
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(start).build());
+    contract.check_in(booking_id.clone(), 12000, 80, "photos-in".to_string()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().status, BookingStatus::Reserved, "Owner alone cannot start the rental");
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start).build());
+    assert!(contract.check_in(booking_id.clone(), 12001, 80, "photos-in".to_string()).is_err(), "Mismatching report should be rejected");
+    contract.check_in(booking_id.clone(), 12000, 80, "photos-in".to_string()).unwrap();
+    assert_eq!(contract.bookings.get(&booking_id).unwrap().status, BookingStatus::Active, "Booking should be active after check-in");
+    testing_env!(get_context("owner1".parse().unwrap()).block_timestamp(start).build());
+
+    // odometer cannot go backwards
+    assert!(contract.check_out(booking_id.clone(), 11000, 50, "photos-out".to_string()).is_err(), "Odometer rollback should be rejected");
+    contract.check_out(booking_id.clone(), 12150, 50, "photos-out".to_string()).unwrap();
+    testing_env!(get_context("user1".parse().unwrap()).block_timestamp(start).build());
+    contract.check_out(booking_id.clone(), 12150, 50, "photos-out".to_string()).unwrap();
+
+    let summary = contract.get_trip_summary(booking_id).unwrap();
+    assert_eq!(summary.distance, 150, "Distance mismatch");
+    assert_eq!(summary.fuel_used, 30, "Fuel used mismatch");
+    assert!(summary.fully_signed, "Both reports should be co-signed");
+}
+
 // Test helper functions
 #[tokio::test]
 async fn test_is_owner() {
@@ -243,12 +1236,12 @@ async fn test_list_available_cars() {
     contract.add_car("car2".to_string(), "owner1".to_string(), 2000000000000000000000).unwrap();
     
     // Book car2
-    contract.book_car(
+    book_car(&mut contract,
         "car2".to_string(),
         "user1".to_string(),
         now,
         now + 3600000000000, // Book for 1 hour from now
-        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000), // 0.1 NEAR deposit
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000), // 0.1 NEAR deposit
     ).unwrap();
     
     let available_cars = contract.list_available_cars().unwrap();
@@ -265,20 +1258,20 @@ async fn test_list_user_bookings() {
     contract.add_car("car1".to_string(), "owner1".to_string(), 2000000000000000000000).unwrap();
     
     let now = near_sdk::env::block_timestamp();
-    contract.book_car(
+    book_car(&mut contract,
         "car1".to_string(),
         "user1".to_string(),
         now,
         now + 3600000000000, // Book for 1 hour from now
-        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000), // 0.1 NEAR deposit
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000), // 0.1 NEAR deposit
     ).unwrap();
     
-    contract.book_car(
+    book_car(&mut contract,
         "car1".to_string(),
         "user2".to_string(),
         now + 3600000000000, // Book after user1's booking
         now + 7200000000000, // Book for 1 hour from that time
-        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000), // 0.1 NEAR deposit
+        near_sdk::NearToken::from_yoctonear(100_000_000_000_000_000_000_000), // 0.1 NEAR deposit
     ).unwrap();
     
     let user1_bookings = contract.list_user_bookings("user1".to_string()).unwrap();